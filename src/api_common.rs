@@ -13,13 +13,72 @@ where
         .map_err(|_| serde::de::Error::custom(format!("invalid number: {}", s)))
 }
 
+/// Serialize BigInts as strings, matching what the server sends us.
+pub fn serialize_natural_to_string<S>(num: &Natural, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&num.to_string())
+}
+
+impl FieldClaim {
+    /// Check that the claim makes sense and fits in the selected engine.
+    pub fn validate(&self, high_bases: bool, powers: &PowerSet) -> Result<(), String> {
+        let max_base = match high_bases {
+            false => MAX_SUPPORTED_BASE_NORMAL,
+            true => MAX_SUPPORTED_BASE_HIGH,
+        };
+        if self.base < MIN_SUPPORTED_BASE || self.base > max_base {
+            return Err(format!(
+                "base {} is outside the supported range {}..={}",
+                self.base, MIN_SUPPORTED_BASE, max_base
+            ));
+        }
+        if powers.is_square_cube() && self.base % 5 == 1 {
+            return Err(format!("base {} cannot be 1 mod 5", self.base));
+        }
+        if self.search_start > self.search_end {
+            return Err(format!(
+                "search start {} is after search end {}",
+                self.search_start, self.search_end
+            ));
+        }
+        if self.search_range != &self.search_end - &self.search_start {
+            return Err(format!(
+                "search range {} does not match {}..{}",
+                self.search_range, self.search_start, self.search_end
+            ));
+        }
+        let (range_start, range_end) = get_power_set_range(self.base, powers);
+        if self.search_start < range_start || self.search_end > range_end {
+            return Err(format!(
+                "search range {}..{} is outside the range for base {}: {}..{}",
+                self.search_start, self.search_end, self.base, range_start, range_end
+            ));
+        }
+        if !high_bases && u128::try_from(&self.search_end).is_err() {
+            return Err(format!(
+                "search end {} does not fit in the integer engine",
+                self.search_end
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Generate a field offline for benchmark testing.
-pub fn get_field_benchmark(base: Option<u32>, range: Option<u32>) -> FieldClaim {
+pub fn get_field_benchmark(base: Option<u32>, range: Option<u32>, powers: &PowerSet) -> FieldClaim {
     let base = base.unwrap_or(BENCHMARK_DEFAULT_BASE);
-    if base % 5 == 1 {
+    if powers.is_square_cube() && base % 5 == 1 {
         panic!("Invalid base {}! Base cannot be 1 mod 5.", base)
     }
-    let (search_start, range_end) = get_base_range(base);
+    let (search_start, range_end) = get_power_set_range(base, powers);
+    if search_start == range_end {
+        panic!(
+            "Invalid base {}! No numbers have exactly {} digits in n^{} and n^{}.",
+            base, base, powers.low, powers.high
+        )
+    }
     let range = Natural::from(range.unwrap_or(BUCNHMARK_DEFAULT_RANGE));
     let search_end = range_end.min(&search_start + &range);
     let search_range = &search_end - &search_start;
@@ -33,82 +92,365 @@ pub fn get_field_benchmark(base: Option<u32>, range: Option<u32>) -> FieldClaim
     };
 }
 
+/// The options sent to the server when claiming a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimRequest {
+    pub mode: Mode,
+    pub username: String,
+    pub base: Option<u32>,
+    pub max_range: Option<u32>,
+    pub field: Option<u32>,
+    pub max_base: u32,
+}
+
+impl ClaimRequest {
+    /// Build a claim request from the CLI options.
+    pub fn new(
+        mode: &Mode,
+        high_bases: &bool,
+        username: &str,
+        base: &Option<u32>,
+        range: &Option<u32>,
+        field: &Option<u32>,
+    ) -> Self {
+        ClaimRequest {
+            mode: *mode,
+            username: username.to_owned(),
+            base: *base,
+            max_range: *range,
+            field: *field,
+            max_base: match high_bases {
+                false => MAX_SUPPORTED_BASE_NORMAL,
+                true => MAX_SUPPORTED_BASE_HIGH,
+            },
+        }
+    }
+}
+
+/// Check that the API base is an absolute http(s) URL we can append endpoints to.
+pub fn parse_api_base(api_base: &str) -> Result<Url, String> {
+    let url = Url::parse(api_base).map_err(|e| format!("Invalid API base {}: {}", api_base, e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!(
+            "Invalid API base {}: must be http or https",
+            api_base
+        ));
+    }
+    if url.cannot_be_a_base() || url.host().is_none() {
+        return Err(format!("Invalid API base {}: missing host", api_base));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "Invalid API base {}: cannot contain a query or fragment",
+            api_base
+        ));
+    }
+    Ok(url)
+}
+
+/// Append path segments to the API base, ignoring any trailing slash.
+fn get_endpoint_url(api_base: &Url, segments: &[&str]) -> Url {
+    let mut url = api_base.clone();
+    url.path_segments_mut()
+        .expect("API base was validated")
+        .pop_if_empty()
+        .extend(segments);
+    url
+}
+
+/// Get the path segment for each mode.
+fn get_mode_segment(mode: &Mode) -> &'static str {
+    match mode {
+        Mode::Detailed => "detailed",
+        Mode::Niceonly => "niceonly",
+    }
+}
+
 /// Build a field request url.
-fn get_claim_url(
-    mode: &Mode,
-    high_bases: &bool,
-    api_base: &str,
-    username: &str,
-    base: &Option<u32>,
-    range: &Option<u32>,
-    field: &Option<u32>,
-) -> String {
-    let mut query_url = api_base.to_owned();
-    query_url += &match mode {
-        Mode::Detailed => "/claim/detailed",
-        Mode::Niceonly => "/claim/niceonly",
-    };
-    query_url += &("?username=".to_owned() + &username.to_string());
-    if let Some(base_val) = base {
-        query_url += &("&base=".to_owned() + &base_val.to_string());
+fn get_claim_url(api_base: &Url, request: &ClaimRequest) -> Url {
+    let mut url = get_endpoint_url(api_base, &["claim", get_mode_segment(&request.mode)]);
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("username", &request.username);
+        if let Some(base_val) = request.base {
+            query.append_pair("base", &base_val.to_string());
+        }
+        if let Some(range_val) = request.max_range {
+            query.append_pair("max_range", &range_val.to_string());
+        }
+        if let Some(field_id_val) = request.field {
+            query.append_pair("field", &field_id_val.to_string());
+        }
+        query.append_pair("max_base", &request.max_base.to_string());
+    }
+    url
+}
+
+/// Options for connecting to the server.
+#[derive(Debug, Clone)]
+pub struct ApiOptions {
+    pub api_base: String,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub proxy: Option<String>,
+    pub ca_certs: Vec<PathBuf>,
+}
+
+impl Default for ApiOptions {
+    fn default() -> Self {
+        ApiOptions {
+            api_base: "https://nicenumbers.net/api".to_string(),
+            connect_timeout: API_DEFAULT_CONNECT_TIMEOUT,
+            timeout: API_DEFAULT_TIMEOUT,
+            proxy: None,
+            ca_certs: Vec::new(),
+        }
     }
-    if let Some(range_val) = range {
-        query_url += &("&max_range=".to_owned() + &range_val.to_string());
+}
+
+/// A reusable connection to the server. All requests go through here.
+/// Requests are async and run on the client's own runtime, which clones share along with
+/// their connections. Synchronous code waits for them with `block_on`, while the pipelined
+/// session `spawn`s them so claims and submits carry on while another field is processed.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    client: reqwest::Client,
+    runtime: Arc<tokio::runtime::Runtime>,
+    api_base: Url,
+}
+
+impl ApiClient {
+    /// Build a client from the options. Fails if the API base, proxy or certificates are invalid.
+    pub fn new(options: &ApiOptions) -> Result<Self, String> {
+        let api_base = parse_api_base(&options.api_base)?;
+        let mut builder = reqwest::Client::builder()
+            .user_agent(format!("nice-rust/{}", CLIENT_VERSION))
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.timeout);
+        if let Some(proxy) = &options.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| format!("Invalid proxy {}: {}", proxy, e))?;
+            builder = builder.proxy(proxy);
+        }
+        for path in &options.ca_certs {
+            let pem = fs::read(path)
+                .map_err(|e| format!("Error reading certificate {}: {}", path.display(), e))?;
+            let cert = reqwest::Certificate::from_pem(&pem)
+                .map_err(|e| format!("Invalid certificate {}: {}", path.display(), e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| format!("Error building client: {}", e))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(API_WORKER_THREADS)
+            .thread_name("nice-api")
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Error starting runtime: {}", e))?;
+        Ok(ApiClient {
+            client,
+            runtime: Arc::new(runtime),
+            api_base,
+        })
     }
-    if let Some(field_id_val) = field {
-        query_url += &("&field=".to_owned() + &field_id_val.to_string());
+
+    /// Wait for a request (or anything else async) to finish on the client's runtime.
+    /// Panics if called from inside the runtime, such as from a spawned task.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Run a request (or anything else async) in the background on the client's runtime.
+    pub fn spawn<F>(&self, future: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.spawn(future)
+    }
+
+    /// Request a field from the server. Supplies CLI options as query strings.
+    pub async fn get_field(&self, request: &ClaimRequest) -> FieldClaim {
+        let url = get_claim_url(&self.api_base, request);
+        let response = self.send(self.client.get(url)).await;
+        match response.json::<FieldClaim>().await {
+            Ok(claim_data) => claim_data,
+            Err(e) => panic!("Error: {}", e),
+        }
+    }
+
+    /// Request a field we claimed before, such as one saved in a checkpoint.
+    /// Returns None if the server refuses (a 4xx response), since the claim has most likely
+    /// expired or been reassigned. Panic on any other error.
+    pub async fn reclaim_field(&self, request: &ClaimRequest) -> Option<FieldClaim> {
+        let url = get_claim_url(&self.api_base, request);
+        let response = self
+            .execute(self.client.get(url))
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        if response.status().is_client_error() {
+            warn!(
+                field = ?request.field,
+                status = response.status().as_u16(),
+                "server refused to reclaim field"
+            );
+            return None;
+        }
+        let response = check_status(response)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        match response.json::<FieldClaim>().await {
+            Ok(claim_data) => Some(claim_data),
+            Err(e) => panic!("Error: {}", e),
+        }
+    }
+
+    /// Submit field results to the server, failing on network errors or if it didn't take them.
+    pub async fn submit_field(&self, mode: &Mode, submit_data: &FieldSubmit) -> Result<(), String> {
+        let url = get_endpoint_url(&self.api_base, &["submit", get_mode_segment(mode)]);
+        self.try_send(self.client.post(url).json(submit_data))
+            .await
+            .map(|_| ())
+    }
+
+    /// Submit results for the start of a field up to `submit_data.processed_end`,
+    /// so the server can reassign the rest. These go to their own endpoint, so a server
+    /// that doesn't know about partial fields rejects them instead of taking them for the whole field.
+    pub async fn submit_partial_field(
+        &self,
+        mode: &Mode,
+        submit_data: &FieldSubmit,
+    ) -> Result<(), String> {
+        debug_assert!(submit_data.processed_end.is_some());
+        let url = get_endpoint_url(
+            &self.api_base,
+            &["submit", get_mode_segment(mode), "partial"],
+        );
+        self.try_send(self.client.post(url).json(submit_data))
+            .await
+            .map(|_| ())
+    }
+
+    /// Release a claimed field back to the server so it can be reassigned. Panic if there is an error.
+    pub async fn release_field(&self, mode: &Mode, claim_data: &FieldClaim) {
+        let url = get_endpoint_url(&self.api_base, &["release", get_mode_segment(mode)]);
+        self.send(self.client.post(url).json(&serde_json::json!({
+            "id": claim_data.id,
+            "username": claim_data.username,
+        })))
+        .await;
+    }
+
+    /// Send a request, panicking on network errors or if the server didn't like it.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Response {
+        self.try_send(request)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Send a request, failing on network errors or if the server didn't like it.
+    async fn try_send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, String> {
+        check_status(self.execute(request).await?).await
+    }
+
+    /// Send a request, failing only on network errors, whatever the response status.
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let request = request
+            .build()
+            .map_err(|e| format!("Error building request: {}", e))?;
+        let (method, url) = (request.method().clone(), request.url().clone());
+        let before = Instant::now();
+        let response = self.client.execute(request).await.map_err(|e| {
+            warn!(%method, %url, elapsed = ?before.elapsed(), error = %e, "request failed");
+            format!("Network error: {}", e)
+        })?;
+        debug!(
+            %method,
+            %url,
+            status = response.status().as_u16(),
+            elapsed = ?before.elapsed(),
+            "request complete"
+        );
+        Ok(response)
     }
-    query_url += &("&max_base=".to_owned());
-    match high_bases {
-        false => query_url += &(MAX_SUPPORTED_BASE_NORMAL.to_string()),
-        true => query_url += &(MAX_SUPPORTED_BASE_HIGH.to_string()),
-    };
-    query_url
 }
 
-/// Request a field from the server. Supplies CLI options as query strings.
-pub fn get_field_from_server(
-    mode: &Mode,
-    high_bases: &bool,
-    api_base: &str,
-    username: &str,
-    base: &Option<u32>,
-    range: &Option<u32>,
-    field: &Option<u32>,
-) -> FieldClaim {
-    let response = reqwest::blocking::get(&get_claim_url(
-        mode, high_bases, api_base, username, base, range, field,
-    ))
-    .unwrap_or_else(|e| panic!("Error: {}", e));
-    match response.json::<FieldClaim>() {
-        Ok(claim_data) => claim_data,
-        Err(e) => panic!("Error: {}", e),
+/// Fail if the server didn't like a request, with its message if it sent one.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, String> {
+    if response.status().is_success() {
+        return Ok(response); // 👍
+    }
+    match response.text().await {
+        Ok(msg) => Err(format!("Server returned an error: {}", msg)),
+        Err(_) => Err("Server returned an error.".to_string()),
     }
 }
 
-/// Submit field results to the server. Panic if there is an error.
-pub fn submit_field_to_server(mode: &Mode, api_base: &str, submit_data: FieldSubmit) {
-    let url = match mode {
-        Mode::Detailed => format!("{}/submit/detailed", api_base),
-        Mode::Niceonly => format!("{}/submit/niceonly", api_base),
-    };
+/// Stand in for the server on a local port, answering one connection per status line and body in order.
+/// Returns the API base to connect to, and a handle giving back every request received.
+/// Stops early if nothing connects for a few seconds, so a missing request fails a test rather than hanging it.
+#[cfg(test)]
+pub fn serve_mock_api(
+    responses: Vec<(&'static str, String)>,
+) -> (String, thread::JoinHandle<Vec<String>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
 
-    let response = reqwest::blocking::Client::new()
-        .post(&url)
-        .json(&submit_data)
-        .send();
-    match response {
-        Ok(response) => {
-            if response.status().is_success() {
-                return; // 👍
-            }
-            match response.text() {
-                Ok(msg) => panic!("Server returned an error: {}", msg),
-                Err(_) => panic!("Server returned an error."),
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_base = format!("http://{}/api", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        listener.set_nonblocking(true).unwrap();
+        for (status, body) in responses {
+            let waited = Instant::now();
+            let stream = loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if waited.elapsed() > Duration::from_secs(5) {
+                            return requests;
+                        }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => panic!("{}", e),
+                }
+            };
+            stream.set_nonblocking(false).unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                request.push_str(&line);
             }
+            let mut request_body = vec![0; content_length];
+            reader.read_exact(&mut request_body).unwrap();
+            request.push_str("\r\n");
+            request.push_str(&String::from_utf8(request_body).unwrap());
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            requests.push(request);
         }
-        Err(e) => panic!("Network error: {}", e),
-    }
+        requests
+    });
+    (api_base, server)
 }
 
 #[cfg(test)]
@@ -120,98 +462,259 @@ mod tests {
     fn test_get_claim_url() {
         assert_eq!(
             get_claim_url(
-                &Mode::Detailed,
-                &false,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &None,
-                &None,
-                &None
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(&Mode::Detailed, &false, "anonymous", &None, &None, &None),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/detailed?username=anonymous&max_base=".to_string()
                 + &MAX_SUPPORTED_BASE_NORMAL.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Niceonly,
-                &false,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &None,
-                &None,
-                &None
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(&Mode::Niceonly, &false, "anonymous", &None, &None, &None),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/niceonly?username=anonymous&max_base=".to_string()
                 + &MAX_SUPPORTED_BASE_NORMAL.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Niceonly,
-                &true,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &None,
-                &None,
-                &None
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(&Mode::Niceonly, &true, "anonymous", &None, &None, &None),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/niceonly?username=anonymous&max_base=".to_string()
                 + &MAX_SUPPORTED_BASE_HIGH.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Detailed,
-                &true,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &Some(120),
-                &None,
-                &None
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(
+                    &Mode::Detailed,
+                    &true,
+                    "anonymous",
+                    &Some(120),
+                    &None,
+                    &None
+                ),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/detailed?username=anonymous&base=120&max_base="
                 .to_string()
                 + &MAX_SUPPORTED_BASE_HIGH.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Detailed,
-                &true,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &None,
-                &Some(1000000),
-                &None
-            ),
-            "https://nicenumbers.net/api/claim/detailed?username=anonymous&max_range=1000000&max_base=".to_string()
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(
+                    &Mode::Detailed,
+                    &true,
+                    "anonymous",
+                    &None,
+                    &Some(1000000),
+                    &None
+                ),
+            )
+            .as_str(),
+            "https://nicenumbers.net/api/claim/detailed?username=anonymous&max_range=1000000\
+             &max_base="
+                .to_string()
                 + &MAX_SUPPORTED_BASE_HIGH.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Detailed,
-                &true,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &None,
-                &None,
-                &Some(123456)
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(
+                    &Mode::Detailed,
+                    &true,
+                    "anonymous",
+                    &None,
+                    &None,
+                    &Some(123456)
+                ),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/detailed?username=anonymous&field=123456&max_base="
                 .to_string()
                 + &MAX_SUPPORTED_BASE_HIGH.to_string()
         );
         assert_eq!(
             get_claim_url(
-                &Mode::Niceonly,
-                &true,
-                "https://nicenumbers.net/api",
-                "anonymous",
-                &Some(120),
-                &Some(1000000),
-                &Some(123456)
-            ),
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(
+                    &Mode::Niceonly,
+                    &true,
+                    "anonymous",
+                    &Some(120),
+                    &Some(1000000),
+                    &Some(123456)
+                ),
+            )
+            .as_str(),
             "https://nicenumbers.net/api/claim/niceonly?username=anonymous".to_string()
                 + &"&base=120&max_range=1000000&field=123456&max_base=".to_string()
                 + &MAX_SUPPORTED_BASE_HIGH.to_string()
         );
+        // only ask for high bases when we can process them
+        assert_eq!(
+            ClaimRequest::new(&Mode::Detailed, &false, "anonymous", &None, &None, &None).max_base,
+            97
+        );
+        assert_eq!(
+            ClaimRequest::new(&Mode::Detailed, &true, "anonymous", &None, &None, &None).max_base,
+            200
+        );
+        // trailing slashes on the api base are ignored
+        assert_eq!(
+            get_claim_url(
+                &parse_api_base("https://nicenumbers.net/api/").unwrap(),
+                &ClaimRequest::new(&Mode::Detailed, &false, "anonymous", &None, &None, &None),
+            )
+            .as_str(),
+            "https://nicenumbers.net/api/claim/detailed?username=anonymous&max_base=".to_string()
+                + &MAX_SUPPORTED_BASE_NORMAL.to_string()
+        );
+        // usernames can't inject extra query parameters
+        assert_eq!(
+            get_claim_url(
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(
+                    &Mode::Detailed,
+                    &false,
+                    "evil&max_base=200&field=1#",
+                    &None,
+                    &None,
+                    &None
+                ),
+            )
+            .as_str(),
+            "https://nicenumbers.net/api/claim/detailed?username=evil%26max_base%3D200%26field%3D1%23&max_base=".to_string()
+                + &MAX_SUPPORTED_BASE_NORMAL.to_string()
+        );
+        // spaces and unicode are encoded
+        assert_eq!(
+            get_claim_url(
+                &parse_api_base("https://nicenumbers.net/api").unwrap(),
+                &ClaimRequest::new(&Mode::Niceonly, &false, "ñandú a/b?", &None, &None, &None),
+            )
+            .as_str(),
+            "https://nicenumbers.net/api/claim/niceonly?username=%C3%B1and%C3%BA+a%2Fb%3F&max_base="
+                .to_string() + &MAX_SUPPORTED_BASE_NORMAL.to_string()
+        );
+        // other endpoints are joined the same way
+        assert_eq!(
+            get_endpoint_url(
+                &parse_api_base("http://localhost:8000/").unwrap(),
+                &["submit", "detailed"]
+            )
+            .as_str(),
+            "http://localhost:8000/submit/detailed"
+        );
+    }
+
+    #[test]
+    fn test_fieldclaim_validate() {
+        let valid = FieldClaim {
+            id: 0,
+            username: "anonymous".to_owned(),
+            base: 10,
+            search_start: Natural::from(47_u32),
+            search_end: Natural::from(100_u32),
+            search_range: Natural::from(53_u32),
+        };
+        assert_eq!(valid.validate(false, &PowerSet::SQUARE_CUBE), Ok(()));
+        assert_eq!(valid.validate(true, &PowerSet::SQUARE_CUBE), Ok(()));
+        assert_eq!(
+            get_field_benchmark(Some(97), None, &PowerSet::SQUARE_CUBE)
+                .validate(false, &PowerSet::SQUARE_CUBE),
+            Ok(())
+        );
+        assert_eq!(
+            get_field_benchmark(Some(200), None, &PowerSet::SQUARE_CUBE)
+                .validate(true, &PowerSet::SQUARE_CUBE),
+            Ok(())
+        );
+
+        // bases the engines can't handle
+        assert!(get_field_benchmark(Some(98), None, &PowerSet::SQUARE_CUBE)
+            .validate(false, &PowerSet::SQUARE_CUBE)
+            .is_err());
+        assert!(get_field_benchmark(Some(202), None, &PowerSet::SQUARE_CUBE)
+            .validate(true, &PowerSet::SQUARE_CUBE)
+            .is_err());
+        let too_low = FieldClaim {
+            base: 3,
+            search_start: Natural::ZERO,
+            search_end: Natural::ZERO,
+            search_range: Natural::ZERO,
+            ..valid.clone()
+        };
+        assert!(too_low.validate(false, &PowerSet::SQUARE_CUBE).is_err());
+        let one_mod_five = FieldClaim {
+            base: 11,
+            ..valid.clone()
+        };
+        assert!(one_mod_five
+            .validate(false, &PowerSet::SQUARE_CUBE)
+            .is_err());
+
+        // inconsistent bounds
+        let backwards = FieldClaim {
+            search_start: Natural::from(100_u32),
+            search_end: Natural::from(47_u32),
+            ..valid.clone()
+        };
+        assert!(backwards.validate(false, &PowerSet::SQUARE_CUBE).is_err());
+        let wrong_range = FieldClaim {
+            search_range: Natural::from(54_u32),
+            ..valid.clone()
+        };
+        assert!(wrong_range.validate(false, &PowerSet::SQUARE_CUBE).is_err());
+        let below_base_range = FieldClaim {
+            search_start: Natural::from(46_u32),
+            search_range: Natural::from(54_u32),
+            ..valid.clone()
+        };
+        assert!(below_base_range
+            .validate(false, &PowerSet::SQUARE_CUBE)
+            .is_err());
+        let above_base_range = FieldClaim {
+            search_end: Natural::from(101_u32),
+            search_range: Natural::from(54_u32),
+            ..valid.clone()
+        };
+        assert!(above_base_range
+            .validate(false, &PowerSet::SQUARE_CUBE)
+            .is_err());
+
+        // other powers have their own ranges
+        let cube_fourth = PowerSet::new(3, 4).unwrap();
+        let (search_start, search_end) = get_power_set_range(10, &cube_fourth);
+        let other_powers = FieldClaim {
+            search_range: &search_end - &search_start,
+            search_start,
+            search_end,
+            ..valid.clone()
+        };
+        assert_eq!(other_powers.validate(false, &cube_fourth), Ok(()));
+        assert!(other_powers
+            .validate(false, &PowerSet::SQUARE_CUBE)
+            .is_err());
+        assert!(valid.validate(false, &cube_fourth).is_err());
+    }
+
+    #[test]
+    fn test_parse_api_base() {
+        assert!(parse_api_base("https://nicenumbers.net/api").is_ok());
+        assert!(parse_api_base("https://nicenumbers.net/api/").is_ok());
+        assert!(parse_api_base("http://127.0.0.1:8000").is_ok());
+        assert!(parse_api_base("").is_err());
+        assert!(parse_api_base("nicenumbers.net/api").is_err());
+        assert!(parse_api_base("ftp://nicenumbers.net/api").is_err());
+        assert!(parse_api_base("mailto:nice@wasabipesto.com").is_err());
+        assert!(parse_api_base("https://nicenumbers.net/api?username=evil").is_err());
+        assert!(parse_api_base("https://nicenumbers.net/api#claim").is_err());
     }
 
     #[test]
@@ -234,6 +737,9 @@ mod tests {
             ])),
             near_misses: Some(HashMap::from([("69".to_string(), 10)])),
             nice_list: Some(Vec::from(["69".to_string()])),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
 
         // Serialize the submit_data and expected JSON
@@ -266,4 +772,146 @@ mod tests {
         // Compare JSON values ignoring key order
         assert_eq!(actual_value, expected_value);
     }
+
+    #[test]
+    fn test_api_client_options() {
+        assert!(ApiClient::new(&ApiOptions::default()).is_ok());
+        assert!(ApiClient::new(&ApiOptions {
+            proxy: Some("http://localhost:3128".to_string()),
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(ApiClient::new(&ApiOptions {
+            proxy: Some("not a proxy".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(ApiClient::new(&ApiOptions {
+            ca_certs: vec![PathBuf::from("/nonexistent/ca.pem")],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_api_client_get_field() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        // answer a single claim request with a canned field
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_base = format!("http://{}/api", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).unwrap();
+            let body = r#"{"id":12,"username":"anonymous","base":10,"search_start":"47","search_end":"100","search_range":"53"}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            String::from_utf8_lossy(&request[..len]).to_string()
+        });
+
+        let client = ApiClient::new(&ApiOptions {
+            api_base,
+            ..Default::default()
+        })
+        .unwrap();
+        let claim_data = client.block_on(client.get_field(&ClaimRequest::new(
+            &Mode::Detailed,
+            &false,
+            "anonymous",
+            &None,
+            &None,
+            &None,
+        )));
+        assert_eq!(claim_data.id, 12);
+        assert_eq!(claim_data.search_range, Natural::from(53_u32));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /api/claim/detailed?username=anonymous"));
+        assert!(request
+            .to_lowercase()
+            .contains(&format!("user-agent: nice-rust/{}", CLIENT_VERSION)));
+    }
+
+    #[test]
+    fn test_api_client_reclaim_field() {
+        let request = ClaimRequest::new(
+            &Mode::Niceonly,
+            &false,
+            "anonymous",
+            &None,
+            &None,
+            &Some(12),
+        );
+        let body = r#"{"id":12,"username":"anonymous","base":10,"search_start":"47","search_end":"100","search_range":"53"}"#;
+        let (api_base, server) = serve_mock_api(vec![
+            ("200 OK", body.to_string()),
+            ("404 Not Found", String::new()),
+        ]);
+        let client = ApiClient::new(&ApiOptions {
+            api_base,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // the claim is still ours, and then it isn't
+        assert_eq!(
+            client.block_on(client.reclaim_field(&request)).unwrap().id,
+            12
+        );
+        assert!(client.block_on(client.reclaim_field(&request)).is_none());
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /api/claim/niceonly?username=anonymous&field=12"));
+    }
+
+    #[test]
+    fn test_api_client_submit_partial_field() {
+        let submit_data = FieldSubmit {
+            id: 12,
+            username: "anonymous".to_owned(),
+            client_version: CLIENT_VERSION.to_string(),
+            unique_count: None,
+            near_misses: None,
+            nice_list: Some(vec!["69".to_string()]),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: Some("70".to_string()),
+        };
+
+        // partial results never go to the endpoint for whole fields
+        let (api_base, server) = serve_mock_api(vec![("200 OK", String::new())]);
+        let client = ApiClient::new(&ApiOptions {
+            api_base,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(client
+            .block_on(client.submit_partial_field(&Mode::Niceonly, &submit_data))
+            .is_ok());
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("POST /api/submit/niceonly/partial "));
+        let body = requests[0].rsplit("\r\n").next().unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["processed_end"], "70");
+        assert_eq!(body["nice_list"], serde_json::json!(["69"]));
+
+        // a server that doesn't know the endpoint is an error, not a panic
+        let (api_base, server) = serve_mock_api(vec![("404 Not Found", String::new())]);
+        let client = ApiClient::new(&ApiOptions {
+            api_base,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(client
+            .block_on(client.submit_partial_field(&Mode::Niceonly, &submit_data))
+            .is_err());
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,101 @@
+//! A module for everything about a field that only depends on its base and powers.
+//! The engines check millions of numbers per field, so anything they would otherwise
+//! recompute per number (or per call) is worked out once here and shared by reference.
+
+use super::*;
+
+/// How many residues the bitmask can hold, enough for any base we search.
+const MAX_RESIDUES: u32 = 256;
+
+/// Everything the engines need to know about a field before checking numbers.
+#[derive(Debug, Clone)]
+pub struct BaseContext {
+    /// The base as a machine word.
+    pub base: u32,
+    /// The base as a Natural, for bignum arithmetic.
+    pub base_natural: Natural,
+    /// Which powers of n have to share the digits.
+    pub powers: PowerSet,
+    /// Residues mod (base - 1) that could be nice, one bit each.
+    residue_mask: [u64; (MAX_RESIDUES / 64) as usize],
+    /// Numbers with more unique digits than this are near misses.
+    pub near_misses_cutoff: u32,
+    /// Divisors for pulling digits out of the powers, including the b^k chunk divisors.
+    pub extractor: DigitExtractor,
+    /// How many digits n^low and n^high have at the last number in the field, the most they have in it.
+    pub digit_lengths: (usize, usize),
+    /// Hot loops compiled for this base specifically, if it's a common one.
+    pub kernel: Option<BaseKernel>,
+}
+
+impl BaseContext {
+    /// Precompute everything needed to search a field.
+    pub fn new(claim_data: &FieldClaim, powers: &PowerSet, cutoff: &NearMissCutoff) -> Self {
+        let base = claim_data.base;
+        assert!(base <= MAX_RESIDUES, "Base {} is too large", base);
+        let base_natural = Natural::from(base);
+
+        let mut residue_mask = [0; (MAX_RESIDUES / 64) as usize];
+        for residue in get_power_set_residue_filter(&base, powers) {
+            residue_mask[residue as usize / 64] |= 1 << (residue % 64);
+        }
+
+        // nothing in the field has more digits than the powers of the last number
+        let last = match claim_data.search_end > claim_data.search_start {
+            true => &claim_data.search_end - Natural::ONE,
+            false => claim_data.search_start.clone(),
+        };
+        let (low, high) = powers.get_powers(&last);
+        let digit_lengths = (
+            low.to_digits_asc(&base).len(),
+            high.to_digits_asc(&base).len(),
+        );
+
+        BaseContext {
+            base,
+            base_natural,
+            powers: *powers,
+            residue_mask,
+            near_misses_cutoff: cutoff.get_threshold(base),
+            extractor: DigitExtractor::new(base),
+            digit_lengths,
+            kernel: get_base_kernel(base, powers),
+        }
+    }
+
+    /// Whether numbers with this residue mod (base - 1) could be nice.
+    pub fn is_residue_kept(&self, residue: u32) -> bool {
+        self.residue_mask[residue as usize / 64] & 1 << (residue % 64) != 0
+    }
+
+    /// How many residues mod (base - 1) could be nice.
+    pub fn residues_kept(&self) -> u32 {
+        self.residue_mask.iter().map(|word| word.count_ones()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_context() {
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        let context = BaseContext::new(
+            &claim_data,
+            &PowerSet::SQUARE_CUBE,
+            &NearMissCutoff::default(),
+        );
+        assert_eq!(context.base_natural, Natural::from(10_u32));
+        assert_eq!(context.near_misses_cutoff, 9);
+        assert_eq!(context.digit_lengths, (4, 6));
+        let residue_filter = get_power_set_residue_filter(&10, &PowerSet::SQUARE_CUBE);
+        for residue in 0..9 {
+            assert_eq!(
+                context.is_residue_kept(residue),
+                residue_filter.contains(&residue)
+            );
+        }
+        assert_eq!(context.residues_kept(), residue_filter.len() as u32);
+    }
+}
@@ -23,11 +23,50 @@ pub fn get_base_range(base: u32) -> (Natural, Natural) {
     }
 }
 
+/// Get the range of possible values for a base with any pair of powers.
+/// Square-cube uses the formula above so fields line up with the server.
+pub fn get_power_set_range(base: u32, powers: &PowerSet) -> (Natural, Natural) {
+    match powers.is_square_cube() {
+        true => get_base_range(base),
+        false => get_exact_range(base, powers),
+    }
+}
+
+/// Get the range where every n in `start..end` has exactly `base` digits across both powers.
+fn get_exact_range(base: u32, powers: &PowerSet) -> (Natural, Natural) {
+    let b = Natural::from(base);
+    // the smallest n where n^power has at least this many digits
+    let get_min_num =
+        |power: u32, digits: u32| b.clone().pow(digits as u64 - 1).ceiling_root(power as u64);
+
+    // digit counts only go up with n, so the ranges for each split are contiguous
+    let mut range: Option<(Natural, Natural)> = None;
+    for low_digits in 1..base {
+        let high_digits = base - low_digits;
+        let start = get_min_num(powers.low, low_digits).max(get_min_num(powers.high, high_digits));
+        let end =
+            get_min_num(powers.low, low_digits + 1).min(get_min_num(powers.high, high_digits + 1));
+        if start < end {
+            range = Some(match range {
+                None => (start, end),
+                Some((s, e)) => (s.min(start), e.max(end)),
+            });
+        }
+    }
+    range.unwrap_or((Natural::ZERO, Natural::ZERO))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::str::FromStr;
 
+    /// Count the digits of both powers of a number.
+    fn get_total_digits(num: u32, base: u32, powers: &PowerSet) -> usize {
+        let (low, high) = powers.get_powers(&Natural::from(num));
+        low.to_digits_asc(&base).len() + high.to_digits_asc(&base).len()
+    }
+
     #[test]
     fn test_get_base_range() {
         assert_eq!(
@@ -162,4 +201,39 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_get_power_set_range() {
+        // the square-cube formula sometimes stops one short of the exact range
+        for base in MIN_SUPPORTED_BASE..=MAX_SUPPORTED_BASE_HIGH {
+            let (start, end) = get_power_set_range(base, &PowerSet::SQUARE_CUBE);
+            let (exact_start, exact_end) = get_exact_range(base, &PowerSet::SQUARE_CUBE);
+            assert_eq!(start, exact_start);
+            assert!(end == exact_end || end + Natural::ONE == exact_end);
+        }
+        assert_eq!(
+            get_power_set_range(10, &PowerSet::new(2, 4).unwrap()),
+            (Natural::from(0_u32), Natural::from(0_u32))
+        );
+    }
+
+    #[test]
+    fn test_get_power_set_range_bounds() {
+        for (low, high) in [(2, 4), (3, 4), (2, 5), (1, 2)] {
+            let powers = PowerSet::new(low, high).unwrap();
+            for base in MIN_SUPPORTED_BASE..=16 {
+                let (start, end) = get_power_set_range(base, &powers);
+                if start == end {
+                    continue;
+                }
+                let start = u32::try_from(&start).unwrap();
+                let end = u32::try_from(&end).unwrap();
+                let base_digits = base as usize;
+                assert!(get_total_digits(start - 1, base, &powers) < base_digits);
+                assert_eq!(get_total_digits(start, base, &powers), base_digits);
+                assert_eq!(get_total_digits(end - 1, base, &powers), base_digits);
+                assert!(get_total_digits(end, base, &powers) > base_digits);
+            }
+        }
+    }
 }
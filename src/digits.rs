@@ -0,0 +1,160 @@
+//! A module for pulling the digits out of large numbers quickly.
+//! Rather than one bignum division per digit, we divide by the largest power of the base
+//! that fits in a u64 and peel the digits off each chunk with machine arithmetic.
+//! Splitting huge numbers in half by larger powers first only pays off above about 2048 bits,
+//! and the digits of every field fit in fewer, so that isn't done.
+
+use super::*;
+
+/// Divisors for converting numbers to digits in a specific base.
+#[derive(Debug, Clone)]
+pub struct DigitExtractor {
+    base: Reciprocal,
+    /// How many digits fit in each chunk.
+    chunk_digits: usize,
+    /// base^chunk_digits, the largest power of the base that fits in a u64.
+    chunk_divisor: LimbReciprocal,
+}
+
+impl DigitExtractor {
+    /// Get the chunk divisor for a base.
+    /// This does all the dividing up front, so build one per field and share it.
+    pub fn new(base: u32) -> Self {
+        let base = base as u64;
+        let mut chunk_digits = 1;
+        let mut chunk_divisor = base;
+        while let Some(next) = chunk_divisor.checked_mul(base) {
+            chunk_digits += 1;
+            chunk_divisor = next;
+        }
+        DigitExtractor {
+            base: Reciprocal::new(base),
+            chunk_digits,
+            chunk_divisor: LimbReciprocal::new(chunk_divisor),
+        }
+    }
+
+    /// The base digits are extracted in.
+    pub fn base(&self) -> u32 {
+        self.base.divisor() as u32
+    }
+
+    /// How many digits fit in each chunk.
+    pub fn chunk_digits(&self) -> usize {
+        self.chunk_digits
+    }
+
+    /// Visit each digit of a number, least significant first.
+    /// Stops as soon as `visit` returns false, and returns whether every digit was visited.
+    pub fn for_each_digit<F: FnMut(u32) -> bool>(&self, num: &Natural, mut visit: F) -> bool {
+        self.visit_limbs(&mut num.to_limbs_asc(), &mut visit)
+    }
+
+    /// Visit each digit of a number given as u64 limbs (least significant first) without allocating.
+    /// The limbs are divided down to zero along the way, and are garbage afterwards.
+    pub fn for_each_digit_in_limbs<F: FnMut(u32) -> bool>(
+        &self,
+        limbs: &mut [u64],
+        mut visit: F,
+    ) -> bool {
+        self.visit_limbs(limbs, &mut visit)
+    }
+
+    /// Visit the digits of a number given as u64 limbs, least significant first.
+    fn visit_limbs<F: FnMut(u32) -> bool>(&self, limbs: &mut [u64], visit: &mut F) -> bool {
+        let mut length = limbs.len();
+        loop {
+            while length > 0 && limbs[length - 1] == 0 {
+                length -= 1;
+            }
+
+            // the last chunk has no zeros above it to keep
+            if length <= 1 {
+                let mut chunk = limbs[..length].first().copied().unwrap_or(0);
+                while chunk > 0 {
+                    let (quotient, digit) = self.base.div_rem(chunk);
+                    if !visit(digit as u32) {
+                        return false;
+                    }
+                    chunk = quotient;
+                }
+                return true;
+            }
+
+            // divide the limbs by the chunk divisor in place, keeping the remainder
+            let mut remainder = self.chunk_divisor.div_rem_limbs(&mut limbs[..length]);
+            for _ in 0..self.chunk_digits {
+                let (quotient, digit) = self.base.div_rem(remainder);
+                if !visit(digit as u32) {
+                    return false;
+                }
+                remainder = quotient;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Collect every digit the extractor visits.
+    fn get_digits(extractor: &DigitExtractor, num: &Natural) -> Vec<u32> {
+        let mut digits = Vec::new();
+        extractor.for_each_digit(num, |digit| {
+            digits.push(digit);
+            true
+        });
+        digits
+    }
+
+    #[test]
+    fn test_chunk_divisor() {
+        let extractor = DigitExtractor::new(10);
+        assert_eq!(extractor.chunk_digits(), 19);
+        assert_eq!(extractor.base(), 10);
+        assert_eq!(DigitExtractor::new(16).chunk_digits(), 15);
+        assert_eq!(DigitExtractor::new(120).chunk_digits(), 9);
+        assert_eq!(DigitExtractor::new(200).chunk_digits(), 8);
+    }
+
+    #[test]
+    fn test_for_each_digit() {
+        let nums = [
+            Natural::ZERO,
+            Natural::from(7_u32),
+            Natural::from(u64::MAX),
+            Natural::from(10_u64.pow(19)),
+            Natural::from(u128::MAX),
+            Natural::from_str("16117196090075248994613996554363597629408239219454").unwrap(),
+        ];
+        for base in [2, 4, 8, 10, 16, 40, 97, 120, 200] {
+            let extractor = DigitExtractor::new(base);
+            for num in &nums {
+                for power in [1, 2, 3, 40, 200] {
+                    let num = num.pow(power);
+                    assert_eq!(
+                        get_digits(&extractor, &num),
+                        num.to_digits_asc(&base),
+                        "{} in base {}",
+                        num,
+                        base
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_each_digit_stops() {
+        let extractor = DigitExtractor::new(10);
+        let mut visited = Vec::new();
+        let finished = extractor.for_each_digit(&Natural::from(123456_u32), |digit| {
+            visited.push(digit);
+            digit != 4
+        });
+        assert!(!finished);
+        assert_eq!(visited, vec![6, 5, 4]);
+    }
+}
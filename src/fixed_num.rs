@@ -0,0 +1,214 @@
+//! A module for numbers stored in a fixed number of u64 limbs, so arithmetic never allocates.
+//! The limb engine picks the width per field so the largest power always fits,
+//! but a wrong width would silently give wrong digits, so overflow always panics.
+
+use super::*;
+use std::cmp::Ordering;
+
+/// A number stored as N u64 limbs, least significant first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FixedNum<const N: usize>([u64; N]);
+
+impl<const N: usize> FixedNum<N> {
+    /// Convert from a Natural, if it fits.
+    pub fn from_natural(num: &Natural) -> Option<Self> {
+        let limbs = num.to_limbs_asc();
+        if limbs.len() > N {
+            return None;
+        }
+        let mut fixed = [0; N];
+        fixed[..limbs.len()].copy_from_slice(&limbs);
+        Some(FixedNum(fixed))
+    }
+
+    /// Convert from a u128, which needs at least two limbs.
+    pub fn from_u128(num: u128) -> Self {
+        let mut fixed = [0; N];
+        fixed[0] = num as u64;
+        fixed[1] = (num >> 64) as u64;
+        FixedNum(fixed)
+    }
+
+    /// Convert back to a Natural, for reporting.
+    pub fn to_natural(self) -> Natural {
+        Natural::from_limbs_asc(&self.0)
+    }
+
+    /// Take the limbs, least significant first.
+    pub fn into_limbs(self) -> [u64; N] {
+        self.0
+    }
+
+    /// The limbs, least significant first.
+    pub fn limbs(&self) -> &[u64; N] {
+        &self.0
+    }
+
+    /// Add one in place.
+    pub fn increment(&mut self) {
+        for limb in self.0.iter_mut() {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                return;
+            }
+        }
+        panic!("FixedNum<{}> overflowed", N);
+    }
+
+    /// How many limbs the number takes up, ignoring leading zeros.
+    fn len(&self) -> usize {
+        self.0
+            .iter()
+            .rposition(|&limb| limb != 0)
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Multiply two numbers whose product fits in N limbs. Panics if it doesn't.
+    pub fn mul(&self, other: &Self) -> Self {
+        let (len, other_len) = (self.len(), other.len());
+        let mut product = [0_u64; N];
+        if len == 0 || other_len == 0 {
+            return FixedNum(product);
+        }
+        // the top limbs alone would land past the end
+        assert!(len + other_len <= N + 1, "FixedNum<{}> overflowed", N);
+        for (i, &a) in self.0[..len].iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, &b) in other.0[..other_len].iter().enumerate() {
+                let current = a as u128 * b as u128 + product[i + j] as u128 + carry as u128;
+                product[i + j] = current as u64;
+                carry = (current >> 64) as u64;
+            }
+            // or carried into the limb past the end
+            match product.get_mut(i + other_len) {
+                Some(limb) => *limb = carry,
+                None => assert!(carry == 0, "FixedNum<{}> overflowed", N),
+            }
+        }
+        FixedNum(product)
+    }
+
+    /// Square a number whose square fits in N limbs.
+    pub fn square(&self) -> Self {
+        self.mul(self)
+    }
+
+    /// Raise a number to a power that fits in N limbs.
+    pub fn pow(&self, exponent: u32) -> Self {
+        match exponent {
+            0 => {
+                let mut one = [0; N];
+                one[0] = 1;
+                FixedNum(one)
+            }
+            1 => *self,
+            2 => self.square(),
+            _ => (2..exponent).fold(self.square(), |power, _| power.mul(self)),
+        }
+    }
+
+    /// Raise a number to both powers in a power set, reusing the lower power like `PowerSet::get_powers`.
+    pub fn get_powers(&self, powers: &PowerSet) -> (Self, Self) {
+        let low = self.pow(powers.low);
+        let high = match powers.high - powers.low {
+            1 => low.mul(self),
+            gap => low.mul(&self.pow(gap)),
+        };
+        (low, high)
+    }
+}
+
+impl<const N: usize> Ord for FixedNum<N> {
+    /// Compare from the most significant limb down.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl<const N: usize> PartialOrd for FixedNum<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_fixed_num_conversion() {
+        let num = Natural::from_str("16117196090075248994613996554363597629408239219454").unwrap();
+        assert_eq!(FixedNum::<4>::from_natural(&num).unwrap().to_natural(), num);
+        assert!(FixedNum::<2>::from_natural(&num).is_none());
+        assert_eq!(
+            FixedNum::<3>::from_u128(u128::MAX).to_natural(),
+            Natural::from(u128::MAX)
+        );
+        assert!(
+            FixedNum::<4>::from_natural(&num) < FixedNum::<4>::from_natural(&(&num + Natural::ONE))
+        );
+        assert!(
+            FixedNum::<4>::from_natural(&Natural::from(u64::MAX))
+                < FixedNum::<4>::from_natural(&num)
+        );
+    }
+
+    #[test]
+    fn test_fixed_num_increment() {
+        let mut num = FixedNum::<3>::from_natural(&Natural::from(u128::MAX)).unwrap();
+        num.increment();
+        assert_eq!(num.to_natural(), Natural::from(u128::MAX) + Natural::ONE);
+    }
+
+    #[test]
+    #[should_panic(expected = "FixedNum<1> overflowed")]
+    fn test_fixed_num_increment_overflow() {
+        let mut num = FixedNum::<1>::from_natural(&Natural::from(u64::MAX)).unwrap();
+        num.increment();
+    }
+
+    #[test]
+    fn test_fixed_num_mul_overflow() {
+        // the product only just fits, or only just doesn't
+        let max = FixedNum::<2>::from_natural(&Natural::from(u64::MAX)).unwrap();
+        assert_eq!(
+            max.square().to_natural(),
+            Natural::from(u64::MAX) * Natural::from(u64::MAX)
+        );
+        let big = FixedNum::<2>::from_natural(&(Natural::from(u64::MAX) + Natural::ONE)).unwrap();
+        assert!(std::panic::catch_unwind(|| big.square()).is_err());
+        assert_eq!(
+            big.mul(&max).to_natural(),
+            (Natural::from(u64::MAX) + Natural::ONE) * Natural::from(u64::MAX)
+        );
+        let two = FixedNum::<2>::from_natural(&Natural::from(2_u32)).unwrap();
+        let half = FixedNum::<2>::from_natural(&Natural::from(1_u128 << 127)).unwrap();
+        assert!(std::panic::catch_unwind(|| two.mul(&half)).is_err());
+    }
+
+    #[test]
+    fn test_fixed_num_powers() {
+        let nums = [
+            Natural::ZERO,
+            Natural::from(7_u32),
+            Natural::from(u64::MAX),
+            Natural::from_str("16117196090075248994613996554363597629408239219454").unwrap(),
+            Natural::from_str("2361200745907465389826478539284734209840372896128763297").unwrap(),
+        ];
+        for powers in [
+            PowerSet::SQUARE_CUBE,
+            PowerSet::new(2, 4).unwrap(),
+            PowerSet::new(3, 5).unwrap(),
+        ] {
+            for num in &nums {
+                let fixed = FixedNum::<24>::from_natural(num).unwrap();
+                let (low, high) = fixed.get_powers(&powers);
+                let (expected_low, expected_high) = powers.get_powers(num);
+                assert_eq!(low.to_natural(), expected_low);
+                assert_eq!(high.to_natural(), expected_high);
+            }
+        }
+    }
+}
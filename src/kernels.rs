@@ -0,0 +1,205 @@
+//! A module with copies of the integer engine's hot loops specialized for the most common bases.
+//! With the base known at compile time, dividing by it turns into a multiply, the digit
+//! indicator lives on the stack at its exact size, and the residue filter is baked in as a mask.
+//! The powers are kept in fixed-width limbs like the limb engine, so nothing in the loop allocates.
+//! Only the square-cube search has kernels, anything else uses the generic code.
+
+use super::*;
+
+/// The square-cube kernels for one base, picked at runtime.
+#[derive(Debug, Copy, Clone)]
+pub struct BaseKernel {
+    pub base: u32,
+    pub get_num_uniques: fn(u128) -> u32,
+    pub get_is_nice: fn(u128) -> bool,
+    pub is_residue_kept: fn(u128) -> bool,
+}
+
+/// How many limbs the kernels keep powers in. A u128 cubed is under 2^384.
+const LIMBS: usize = 6;
+
+/// Get the number of digits in a chunk and the chunk divisor, the largest power of the base in a u64.
+const fn get_chunk(base: u64) -> (usize, u64) {
+    let mut digits = 1;
+    let mut divisor = base;
+    while let Some(next) = divisor.checked_mul(base) {
+        digits += 1;
+        divisor = next;
+    }
+    (digits, divisor)
+}
+
+/// Get the square-cube residue filter for a base as a bitmask, one bit per residue.
+const fn get_residue_mask(base: u64) -> u128 {
+    let target_residue = base * (base - 1) / 2 % (base - 1);
+    let mut mask = 0;
+    let mut residue = 0;
+    while residue < base - 1 {
+        if (residue.pow(2) + residue.pow(3)) % (base - 1) == target_residue {
+            mask |= 1 << residue;
+        }
+        residue += 1;
+    }
+    mask
+}
+
+/// The square-cube kernels for a base known at compile time.
+struct Kernel<const BASE: usize>;
+
+impl<const BASE: usize> Kernel<BASE> {
+    const CHUNK_DIGITS: usize = get_chunk(BASE as u64).0;
+    const CHUNK_DIVISOR: LimbReciprocal = LimbReciprocal::new(get_chunk(BASE as u64).1);
+    const RESIDUE_MASK: u128 = get_residue_mask(BASE as u64);
+
+    const KERNEL: BaseKernel = BaseKernel {
+        base: BASE as u32,
+        get_num_uniques: Self::get_num_uniques,
+        get_is_nice: Self::get_is_nice,
+        is_residue_kept: Self::is_residue_kept,
+    };
+
+    /// Visit each digit of a number, least significant first, until `visit` returns false.
+    /// The number is stored as u64 limbs (least significant first), and is consumed in the process.
+    fn for_each_digit<F: FnMut(usize) -> bool>(limbs: &mut [u64], visit: &mut F) -> bool {
+        let mut len = limbs.len();
+        loop {
+            while len > 0 && limbs[len - 1] == 0 {
+                len -= 1;
+            }
+
+            // the last chunk has no zeros above it to keep
+            if len <= 1 {
+                let mut chunk = if len == 1 { limbs[0] } else { 0 };
+                while chunk > 0 {
+                    if !visit((chunk % BASE as u64) as usize) {
+                        return false;
+                    }
+                    chunk /= BASE as u64;
+                }
+                return true;
+            }
+
+            let mut remainder = Self::CHUNK_DIVISOR.div_rem_limbs(&mut limbs[..len]);
+            for _ in 0..Self::CHUNK_DIGITS {
+                if !visit((remainder % BASE as u64) as usize) {
+                    return false;
+                }
+                remainder /= BASE as u64;
+            }
+        }
+    }
+
+    /// Get the count of unique digits in a number's square and cube.
+    fn get_num_uniques(num: u128) -> u32 {
+        let mut digits_indicator = [false; BASE];
+        let mut mark_digit = |digit: usize| {
+            digits_indicator[digit] = true;
+            true
+        };
+        let (square, cube) = FixedNum::<LIMBS>::from_u128(num).get_powers(&PowerSet::SQUARE_CUBE);
+        Self::for_each_digit(&mut square.into_limbs(), &mut mark_digit);
+        Self::for_each_digit(&mut cube.into_limbs(), &mut mark_digit);
+        digits_indicator.iter().filter(|&&seen| seen).count() as u32
+    }
+
+    /// Quickly determine if a number is 100% nice.
+    fn get_is_nice(num: u128) -> bool {
+        let mut digits_indicator = [false; BASE];
+        let mut check_digit = |digit: usize| {
+            let seen = digits_indicator[digit];
+            digits_indicator[digit] = true;
+            !seen
+        };
+        let (square, cube) = FixedNum::<LIMBS>::from_u128(num).get_powers(&PowerSet::SQUARE_CUBE);
+        Self::for_each_digit(&mut square.into_limbs(), &mut check_digit)
+            && Self::for_each_digit(&mut cube.into_limbs(), &mut check_digit)
+    }
+
+    /// Whether a number's residue mod (base - 1) could be nice.
+    fn is_residue_kept(num: u128) -> bool {
+        Self::RESIDUE_MASK >> (num % (BASE as u128 - 1)) & 1 != 0
+    }
+}
+
+/// Generate the dispatch from a runtime base to each compiled kernel.
+macro_rules! base_kernels {
+    ($($base:literal)*) => {
+        /// Get the specialized kernels for a base, if it has any.
+        pub fn get_base_kernel(base: u32, powers: &PowerSet) -> Option<BaseKernel> {
+            if !powers.is_square_cube() {
+                return None;
+            }
+            match base {
+                $($base => Some(Kernel::<$base>::KERNEL),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+// every square-cube base from 40 through 97, skipping those that are 1 mod 5 and have no field
+base_kernels!(
+    40 42 43 44 45 47 48 49 50 52 53 54 55 57 58 59 60 62 63 64 65 67 68 69 70
+    72 73 74 75 77 78 79 80 82 83 84 85 87 88 89 90 92 93 94 95 97
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use residue_filter::get_residue_filter;
+
+    #[test]
+    fn test_get_residue_mask() {
+        for base in 4..=MAX_SUPPORTED_BASE_NORMAL {
+            let mask = get_residue_mask(base as u64);
+            let residue_filter = get_residue_filter(&base);
+            for residue in 0..base - 1 {
+                assert_eq!(mask >> residue & 1 != 0, residue_filter.contains(&residue));
+            }
+        }
+    }
+
+    #[test]
+    fn test_base_kernels() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        assert!(get_base_kernel(40, &PowerSet::new(2, 4).unwrap()).is_none());
+        assert!(get_base_kernel(10, &PowerSet::SQUARE_CUBE).is_none());
+        for base in 40..=MAX_SUPPORTED_BASE_NORMAL {
+            let Some(kernel) = get_base_kernel(base, &PowerSet::SQUARE_CUBE) else {
+                assert_eq!(base % 5, 1);
+                continue;
+            };
+            assert_eq!(kernel.base, base);
+
+            // compare against the generic code on random numbers in the base
+            let (start, end) = get_base_range(base);
+            let (start, end) = (
+                u128::try_from(&start).unwrap(),
+                u128::try_from(&end).unwrap(),
+            );
+            let extractor = DigitExtractor::new(base);
+            for _ in 0..20 {
+                let num = rng.gen_range(start..end);
+                let mut digits_indicator = vec![false; base as usize];
+                let mut unique_digits = true;
+                let num_natural = Natural::from(num);
+                for power in [2, 3] {
+                    extractor.for_each_digit(&(&num_natural).pow(power), |digit| {
+                        unique_digits &= !digits_indicator[digit as usize];
+                        digits_indicator[digit as usize] = true;
+                        true
+                    });
+                }
+                let uniques = digits_indicator.iter().filter(|&&seen| seen).count() as u32;
+                assert_eq!((kernel.get_num_uniques)(num), uniques);
+                assert_eq!((kernel.get_is_nice)(num), unique_digits);
+                let residue = (num % (base as u128 - 1)) as u32;
+                assert_eq!(
+                    (kernel.is_residue_kept)(num),
+                    get_residue_filter(&base).contains(&residue)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! A module for skipping ranges of n whose leading digits already repeat.
+//! Over a short run of n the most significant digits of n^2 and n^3 don't change.
+//! If those fixed digits already contain a repeat, nothing in the run can be nice.
+
+use super::*;
+
+/// Ranges this small are searched number by number instead of being split further.
+pub const LEADING_DIGITS_LEAF_SIZE: u32 = 256;
+
+/// Get the leading digits shared by the first and last power in a range.
+/// Powers only go up with n, so every power in between shares them too.
+fn get_fixed_digits<'a>(first: &'a [u32], last: &[u32]) -> &'a [u32] {
+    let length = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    &first[..length]
+}
+
+/// Everything needed to split up a field, so it doesn't have to be passed around separately.
+struct RangeSplitter<'a> {
+    base: u32,
+    base_natural: Natural,
+    powers: &'a PowerSet,
+    leaf_size: Natural,
+    ranges: Vec<(Natural, Natural)>,
+}
+
+impl<'a> RangeSplitter<'a> {
+    /// Keep a range for searching, joining it to the previous one if they touch.
+    fn keep(&mut self, start: Natural, end: Natural) {
+        match self.ranges.last_mut() {
+            Some((_, previous_end)) if *previous_end == start => *previous_end = end,
+            _ => self.ranges.push((start, end)),
+        }
+    }
+
+    /// The smallest n where n^power is at least `value * base^shift`.
+    fn get_min_num(&self, value: Natural, shift: usize, power: u32) -> Natural {
+        (value * (&self.base_natural).pow(shift as u64)).ceiling_root(power as u64)
+    }
+
+    /// Search `start..end`, dropping any part of it whose fixed leading digits repeat.
+    fn split(&mut self, start: Natural, end: Natural) {
+        if start >= end {
+            return;
+        }
+        if &end - &start <= self.leaf_size {
+            self.keep(start, end);
+            return;
+        }
+        let last = &end - Natural::ONE;
+        let (first_low, first_high) = self.powers.get_powers(&start);
+        let (last_low, last_high) = self.powers.get_powers(&last);
+        let first_low = first_low.to_digits_desc(&self.base);
+        let last_low = last_low.to_digits_desc(&self.base);
+        let first_high = first_high.to_digits_desc(&self.base);
+        let last_high = last_high.to_digits_desc(&self.base);
+
+        // if either power gains a digit partway through, split where it does
+        for (first, last, power) in [
+            (&first_low, &last_low, self.powers.low),
+            (&first_high, &last_high, self.powers.high),
+        ] {
+            if first.len() != last.len() {
+                let middle = self.get_min_num(Natural::ONE, first.len(), power);
+                self.split(start, middle.clone());
+                self.split(middle, end);
+                return;
+            }
+        }
+
+        // check the digits every number in the range has in common
+        let low_fixed = get_fixed_digits(&first_low, &last_low);
+        let high_fixed = get_fixed_digits(&first_high, &last_high);
+        let mut digits_indicator = vec![false; self.base as usize];
+        for &digit in low_fixed.iter().chain(high_fixed) {
+            if digits_indicator[digit as usize] {
+                return;
+            }
+            digits_indicator[digit as usize] = true;
+        }
+
+        // split on the next digit of the lower power, skipping any that are already taken
+        let position = low_fixed.len();
+        let shift = first_low.len() - position - 1;
+        let prefix = Natural::from_digits_desc(&self.base, low_fixed.iter().copied()).unwrap();
+        let prefix = prefix * &self.base_natural;
+        let (first_digit, last_digit) = (first_low[position], last_low[position]);
+        for digit in first_digit..=last_digit {
+            if digits_indicator[digit as usize] {
+                continue;
+            }
+            let child_start = match digit == first_digit {
+                true => start.clone(),
+                false => self.get_min_num(&prefix + Natural::from(digit), shift, self.powers.low),
+            };
+            let child_end = match digit == last_digit {
+                true => end.clone(),
+                false => {
+                    self.get_min_num(&prefix + Natural::from(digit + 1), shift, self.powers.low)
+                }
+            };
+            self.split(child_start, child_end);
+        }
+    }
+}
+
+/// Get the parts of `start..end` that could still hold a nice number, in order.
+/// Everything left out has a repeated digit among the leading digits of its powers.
+pub fn get_candidate_ranges(
+    base: u32,
+    powers: &PowerSet,
+    start: &Natural,
+    end: &Natural,
+    leaf_size: u32,
+) -> Vec<(Natural, Natural)> {
+    let mut splitter = RangeSplitter {
+        base,
+        base_natural: Natural::from(base),
+        powers,
+        leaf_size: Natural::from(leaf_size.max(1)),
+        ranges: Vec::new(),
+    };
+    splitter.split(start.clone(), end.clone());
+    splitter.ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the candidate ranges are in order and drop nothing that could be nice.
+    fn check_candidate_ranges(
+        base: u32,
+        powers: &PowerSet,
+        start: u128,
+        end: u128,
+        leaf_size: u32,
+    ) {
+        let ranges = get_candidate_ranges(
+            base,
+            powers,
+            &Natural::from(start),
+            &Natural::from(end),
+            leaf_size,
+        );
+        let ranges: Vec<(u128, u128)> = ranges
+            .iter()
+            .map(|(s, e)| (u128::try_from(s).unwrap(), u128::try_from(e).unwrap()))
+            .collect();
+        let claim_data = FieldClaim {
+            id: 0,
+            username: "test".to_owned(),
+            base,
+            search_start: Natural::from(start),
+            search_end: Natural::from(end),
+            search_range: Natural::from(end - start),
+        };
+        let context = BaseContext::new(&claim_data, powers, &NearMissCutoff::default());
+        let mut previous_end = start;
+        for &(range_start, range_end) in &ranges {
+            assert!(previous_end <= range_start && range_start < range_end);
+            for num in previous_end..range_start {
+                assert!(process_integer::get_num_uniques(num, &context) < base);
+            }
+            previous_end = range_end;
+        }
+        assert!(previous_end <= end);
+        for num in previous_end..end {
+            assert!(process_integer::get_num_uniques(num, &context) < base);
+        }
+    }
+
+    #[test]
+    fn test_get_fixed_digits() {
+        assert_eq!(get_fixed_digits(&[1, 2, 3, 4], &[1, 2, 5, 0]), &[1, 2]);
+        assert_eq!(get_fixed_digits(&[1, 2, 3], &[1, 2, 3]), &[1, 2, 3]);
+        assert!(get_fixed_digits(&[1, 2, 3], &[2, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_get_candidate_ranges_b10() {
+        // the whole base, split all the way down, still finds 69
+        let ranges = get_candidate_ranges(
+            10,
+            &PowerSet::SQUARE_CUBE,
+            &Natural::from(47_u32),
+            &Natural::from(100_u32),
+            1,
+        );
+        let kept = ranges.iter().fold(Natural::ZERO, |acc, (s, e)| acc + e - s);
+        assert!(kept < 53_u32);
+        assert!(ranges.iter().any(|(s, e)| *s <= 69_u32 && *e > 69_u32));
+        check_candidate_ranges(10, &PowerSet::SQUARE_CUBE, 47, 100, 1);
+        check_candidate_ranges(10, &PowerSet::new(3, 4).unwrap(), 18, 22, 1);
+    }
+
+    #[test]
+    fn test_get_candidate_ranges() {
+        for base in [12, 13, 14, 17, 20, 24, 30] {
+            let (start, end) = get_base_range(base);
+            let start = u128::try_from(&start).unwrap();
+            let end = u128::try_from(&end).unwrap().min(start + 20000);
+            for leaf_size in [1, 7, 64] {
+                check_candidate_ranges(base, &PowerSet::SQUARE_CUBE, start, end, leaf_size);
+            }
+        }
+        check_candidate_ranges(12, &PowerSet::new(2, 4).unwrap(), 78, 144, 1);
+    }
+
+    #[test]
+    fn test_get_candidate_ranges_skips() {
+        // a large field in base 40 drops most of its numbers
+        let start = 1916284264916_u128;
+        let ranges = get_candidate_ranges(
+            40,
+            &PowerSet::SQUARE_CUBE,
+            &Natural::from(start),
+            &Natural::from(start + 1_000_000),
+            LEADING_DIGITS_LEAF_SIZE,
+        );
+        let kept = ranges.iter().fold(Natural::ZERO, |acc, (s, e)| acc + e - s);
+        assert!(kept < 500_000_u32);
+    }
+}
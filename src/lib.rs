@@ -6,69 +6,166 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::env;
-use std::time::Instant;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 extern crate rayon;
 use rayon::prelude::*;
 
 extern crate malachite;
 use malachite::natural::Natural;
-use malachite::num::arithmetic::traits::{CeilingRoot, DivAssignRem, FloorRoot, Mod, Pow};
+use malachite::num::arithmetic::traits::{CeilingRoot, FloorRoot, Mod, Pow};
 use malachite::num::basic::traits::{One, Zero};
 use malachite::num::conversion::traits::Digits;
+use malachite::num::logic::traits::SignificantBits;
 
 extern crate reqwest;
+use reqwest::Url;
 extern crate serde;
+extern crate serde_json;
+extern crate tokio;
 use serde::{Deserialize, Serialize};
 
 extern crate clap;
 use clap::ValueEnum; // have to derive enum for cli
 
+extern crate ctrlc;
+
+extern crate rand;
+use rand::Rng;
+
+extern crate tracing;
+extern crate tracing_appender;
+extern crate tracing_subscriber;
+use tracing::{debug, error, info, warn, Level};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
+
 const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const MIN_SUPPORTED_BASE: u32 = 4;
 const MAX_SUPPORTED_BASE_NORMAL: u32 = 97;
-const MAX_SUPPORTED_BASE_HIGH: u32 = 120;
-const NEAR_MISS_CUTOFF_PERCENT: f32 = 0.9;
+const MAX_SUPPORTED_BASE_HIGH: u32 = 200;
 const BENCHMARK_DEFAULT_BASE: u32 = 40;
 const BUCNHMARK_DEFAULT_RANGE: u32 = 100000;
+const API_DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const API_DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const API_WORKER_THREADS: usize = 2;
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SESSION_RETRY_DELAY_MIN: Duration = Duration::from_secs(5);
+const SESSION_RETRY_DELAY_MAX: Duration = Duration::from_secs(300);
 
 mod api_common;
-use api_common::{
-    deserialize_string_to_natural, get_field_benchmark, get_field_from_server,
-    submit_field_to_server,
-};
+use api_common::{deserialize_string_to_natural, get_field_benchmark, serialize_natural_to_string};
+pub use api_common::{ApiClient, ApiOptions, ClaimRequest};
+
+pub mod process_integer;
+pub mod process_limbs;
+pub mod process_natural;
+
+mod near_miss;
+use self::near_miss::TopNearMisses;
+pub use self::near_miss::{NearMissCutoff, NearMissDetail, NearMissOptions};
+
+mod power_set;
+pub use self::power_set::PowerSet;
+
+mod reciprocal;
+use self::reciprocal::{LimbReciprocal, Reciprocal};
+
+mod fixed_num;
+use self::fixed_num::FixedNum;
+
+mod digits;
+pub use self::digits::DigitExtractor;
+
+mod kernels;
+use self::kernels::{get_base_kernel, BaseKernel};
+
+mod base_context;
+pub use self::base_context::BaseContext;
 
-mod process_integer;
-mod process_natural;
+mod leading_digits;
+use self::leading_digits::{get_candidate_ranges, LEADING_DIGITS_LEAF_SIZE};
 
 mod residue_filter;
-use self::residue_filter::get_residue_filter;
+use self::residue_filter::get_power_set_residue_filter;
 
 mod base_range;
-use self::base_range::get_base_range;
+pub use self::base_range::{get_base_range, get_power_set_range};
+
+mod shutdown;
+use self::shutdown::{interrupt_field, load_checkpoint, remove_checkpoint};
+
+mod partial;
+pub use self::partial::{
+    get_sub_claim, merge_field_submits, process_field_until, FieldProgress, StopCondition,
+};
+
+mod logging;
+pub use self::logging::{init_logging, LogFormat, LogOptions, LogRotation};
+
+mod metrics;
+pub use self::metrics::{serve_metrics, Metrics};
+
+mod results_store;
+pub use self::results_store::{
+    append_record, get_base_totals, read_records, BaseTotals, FieldRecord,
+};
+
+mod verify;
+use self::verify::verify_field;
+
+mod session;
+pub use self::session::{
+    parse_duration, run_pipelined_session, run_session, PipelineOptions, SessionLimits,
+    SessionSummary,
+};
+pub use self::shutdown::{ShutdownAction, ShutdownSignal};
 
 /// Each possible search mode the server and client supports.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
     Detailed,
     Niceonly,
 }
 
 /// A field returned from the server. Used as input for processing.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FieldClaim {
     pub id: u32,
     pub username: String,
     pub base: u32,
-    #[serde(deserialize_with = "deserialize_string_to_natural")]
+    #[serde(
+        serialize_with = "serialize_natural_to_string",
+        deserialize_with = "deserialize_string_to_natural"
+    )]
     pub search_start: Natural,
-    #[serde(deserialize_with = "deserialize_string_to_natural")]
+    #[serde(
+        serialize_with = "serialize_natural_to_string",
+        deserialize_with = "deserialize_string_to_natural"
+    )]
     pub search_end: Natural,
-    #[serde(deserialize_with = "deserialize_string_to_natural")]
+    #[serde(
+        serialize_with = "serialize_natural_to_string",
+        deserialize_with = "deserialize_string_to_natural"
+    )]
     pub search_range: Natural,
 }
 
 /// The compiled results sent to the server after processing. Options for both modes.
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct FieldSubmit {
     pub id: u32,
     pub username: String,
@@ -76,63 +173,441 @@ pub struct FieldSubmit {
     pub unique_count: Option<HashMap<u32, u32>>,
     pub near_misses: Option<HashMap<String, u32>>,
     pub nice_list: Option<Vec<String>>,
+    /// The numbers with the most unique digits in the field, best first. Only sent if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_near_misses: Option<Vec<(String, u32)>>,
+    /// Which digits are missing or repeated for each reported number. Only sent if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near_miss_details: Option<HashMap<String, NearMissDetail>>,
+    /// Where processing stopped, if only the start of the field up to here was processed.
+    /// The server keeps these results and reassigns the rest of the field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_end: Option<String>,
 }
 
-/// Run the program following the specified flow.
-pub fn run(
-    mode: Mode,
-    api_base: String,
-    username: String,
-    quiet: bool,
-    verbose: bool,
-    benchmark: bool,
-    parallel: bool,
-    high_bases: bool,
-    base: Option<u32>,
-    range: Option<u32>,
-    field: Option<u32>,
-) {
-    let claim_data = if benchmark {
-        get_field_benchmark(base, range)
-    } else {
-        get_field_from_server(
-            &mode,
-            &high_bases,
-            &api_base,
-            &username,
-            &base,
-            &range,
-            &field,
-        )
-    };
-    if !quiet {
-        println!("{:?}", claim_data);
+/// Options for running the client, usually populated from the command line.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: Mode,
+    pub username: String,
+    pub benchmark: bool,
+    pub parallel: bool,
+    pub grain_size: usize,
+    pub high_bases: bool,
+    pub near_misses: NearMissOptions,
+    pub powers: PowerSet,
+    pub base: Option<u32>,
+    pub range: Option<u32>,
+    pub field: Option<u32>,
+    pub on_shutdown: ShutdownAction,
+    pub checkpoint_file: PathBuf,
+    pub verify: bool,
+    pub verify_sample: u32,
+    pub metrics: Metrics,
+    pub results_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::Detailed,
+            username: "anonymous".to_string(),
+            benchmark: false,
+            parallel: false,
+            grain_size: process_integer::DEFAULT_GRAIN_SIZE,
+            high_bases: false,
+            near_misses: NearMissOptions::default(),
+            powers: PowerSet::default(),
+            base: None,
+            range: None,
+            field: None,
+            on_shutdown: ShutdownAction::Finish,
+            checkpoint_file: PathBuf::from("nice-checkpoint.json"),
+            verify: false,
+            verify_sample: 1000,
+            metrics: Metrics::default(),
+            results_file: None,
+        }
     }
-    let before = Instant::now();
+}
 
-    // process range & compile results
-    let submit_data: FieldSubmit = match high_bases {
+/// A field that was fully processed, along with how long it took.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FieldResult {
+    pub mode: Mode,
+    pub claim_data: FieldClaim,
+    pub submit_data: FieldSubmit,
+    pub elapsed: Duration,
+}
+
+impl FieldResult {
+    /// Every nice number found in the field, whichever mode it was processed in.
+    pub fn nice_numbers(&self) -> Vec<String> {
+        let mut nice_numbers = Vec::new();
+        if let Some(near_misses) = &self.submit_data.near_misses {
+            // a near miss with every digit is a nice number too
+            nice_numbers.extend(
+                near_misses
+                    .iter()
+                    .filter(|(_, &uniques)| uniques == self.claim_data.base)
+                    .map(|(num, _)| num.clone()),
+            );
+        }
+        if let Some(nice_list) = &self.submit_data.nice_list {
+            nice_numbers.extend(nice_list.iter().cloned());
+        }
+        nice_numbers
+    }
+}
+
+/// The results for the start of a field, and the number after the last one processed.
+pub type PartialProgress = (FieldSubmit, Natural);
+
+/// How a single run ended.
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// The field was fully processed (and submitted, unless benchmarking).
+    Completed(Box<FieldResult>),
+    /// The field was interrupted and saved to the checkpoint file.
+    Checkpointed { id: u32 },
+    /// The field was interrupted and released back to the server.
+    Released { id: u32 },
+    /// The field was interrupted, the numbers it got through were submitted, and the rest released.
+    /// The result's claim only covers the part that was processed.
+    PartiallySubmitted(Box<FieldResult>),
+    /// The field was interrupted and dropped without notifying anyone.
+    Abandoned { id: u32 },
+    /// The claim or our results failed validation, so the field was released back to the server.
+    Rejected { id: u32, reason: String },
+}
+
+/// A short name for the engine selected by the options.
+/// High bases above u128 use fixed-width limbs, the Natural engine only handles the rest.
+pub fn get_engine_name(high_bases: bool, parallel: bool, base: u32) -> &'static str {
+    match (high_bases, parallel) {
+        (false, false) => "integer",
+        (false, true) => "integer-parallel",
+        (true, _) if base > MAX_SUPPORTED_BASE_NORMAL => "limbs",
+        (true, _) => "natural",
+    }
+}
+
+/// Process a claimed field with the engine selected by the options.
+/// `grain_size` is how many numbers each parallel task covers in detailed mode.
+#[allow(clippy::too_many_arguments)]
+pub fn process_field(
+    mode: &Mode,
+    high_bases: bool,
+    parallel: bool,
+    grain_size: usize,
+    near_miss_options: &NearMissOptions,
+    powers: &PowerSet,
+    claim_data: &FieldClaim,
+) -> FieldSubmit {
+    let context = BaseContext::new(claim_data, powers, &near_miss_options.cutoff);
+    debug!(
+        ?mode,
+        engine = get_engine_name(high_bases, parallel, claim_data.base),
+        base = claim_data.base,
+        %powers,
+        base_kernel = !high_bases && context.kernel.is_some(),
+        "selected engine"
+    );
+    match high_bases {
         false => match mode {
-            Mode::Detailed => process_integer::process_detailed(&claim_data, parallel),
-            Mode::Niceonly => process_integer::process_niceonly(&claim_data, parallel),
+            Mode::Detailed => process_integer::process_detailed(
+                claim_data,
+                &context,
+                parallel,
+                grain_size,
+                near_miss_options,
+            ),
+            Mode::Niceonly => process_integer::process_niceonly(claim_data, &context, parallel),
+        },
+        true if claim_data.base > MAX_SUPPORTED_BASE_NORMAL => match mode {
+            Mode::Detailed => {
+                process_limbs::process_detailed(claim_data, &context, near_miss_options)
+            }
+            Mode::Niceonly => process_limbs::process_niceonly(claim_data, &context),
         },
         true => match mode {
-            Mode::Detailed => process_natural::process_detailed(&claim_data),
-            Mode::Niceonly => process_natural::process_niceonly(&claim_data),
+            Mode::Detailed => {
+                process_natural::process_detailed(claim_data, &context, near_miss_options)
+            }
+            Mode::Niceonly => process_natural::process_niceonly(claim_data, &context),
         },
+    }
+}
+
+/// Claim the next field to work on: a benchmark field, an interrupted field
+/// saved to the checkpoint file, or a fresh field from the server.
+/// A field from the checkpoint comes with the results so far and where they end, if there are any.
+pub async fn claim_field(
+    config: &Config,
+    client: &ApiClient,
+) -> (Mode, FieldClaim, Option<PartialProgress>) {
+    let (mode, claim_data, progress) = if config.benchmark {
+        let claim_data = get_field_benchmark(config.base, config.range, &config.powers);
+        (config.mode, claim_data, None)
+    } else if let Some(resumed) = reclaim_checkpoint(config, client).await {
+        resumed
+    } else {
+        let claim_data = client
+            .get_field(&ClaimRequest::new(
+                &config.mode,
+                &config.high_bases,
+                &config.username,
+                &config.base,
+                &config.range,
+                &config.field,
+            ))
+            .await;
+        (config.mode, claim_data, None)
+    };
+    info!(
+        id = claim_data.id,
+        base = claim_data.base,
+        search_start = %claim_data.search_start,
+        search_end = %claim_data.search_end,
+        "claimed field"
+    );
+    debug!("{:?}", claim_data);
+    config.metrics.record_claim(&claim_data);
+    (mode, claim_data, progress)
+}
+
+/// Reclaim the field saved in the checkpoint file, if there is one and the server still lets us.
+/// The checkpoint is forgotten once the server has answered either way, and one that
+/// can't be read is ignored, so a dead checkpoint never stops us claiming fresh fields.
+async fn reclaim_checkpoint(
+    config: &Config,
+    client: &ApiClient,
+) -> Option<(Mode, FieldClaim, Option<PartialProgress>)> {
+    let checkpoint = match load_checkpoint(&config.checkpoint_file) {
+        Ok(checkpoint) => checkpoint?,
+        Err(e) => {
+            error!(error = %e, "ignoring checkpoint");
+            return None;
+        }
+    };
+    info!(id = checkpoint.claim.id, "resuming field from checkpoint");
+    let claim_data = client
+        .reclaim_field(&ClaimRequest::new(
+            &checkpoint.mode,
+            &config.high_bases,
+            &config.username,
+            &None,
+            &None,
+            &Some(checkpoint.claim.id),
+        ))
+        .await;
+    if let Err(e) = remove_checkpoint(&config.checkpoint_file) {
+        error!(error = %e, "could not remove checkpoint");
+    }
+    let Some(claim_data) = claim_data else {
+        info!(id = checkpoint.claim.id, "claiming a new field instead");
+        return None;
     };
 
-    if !quiet {
-        println!("{:?}", submit_data);
+    // the results so far only count for exactly the same field
+    let same_field = claim_data.search_start == checkpoint.claim.search_start
+        && claim_data.search_end == checkpoint.claim.search_end;
+    let progress = checkpoint
+        .progress
+        .filter(|progress| {
+            same_field
+                && progress.next > claim_data.search_start
+                && progress.next < claim_data.search_end
+        })
+        .map(|progress| (progress.submit_data, progress.next));
+    if let Some((_, next)) = &progress {
+        info!(id = claim_data.id, %next, "picking up where the checkpoint left off");
     }
-    if benchmark || verbose {
-        println!("Elapsed time: {:.3?}", before.elapsed());
-        println!(
-            "Hash rate:    {:.3e}",
-            f64::try_from(&claim_data.search_range).unwrap() / before.elapsed().as_secs_f64()
+    Some((checkpoint.mode, claim_data, progress))
+}
+
+/// Process a claimed field without submitting it, carrying on from `progress` if it was resumed.
+/// If the shutdown signal is triggered before or during the field, `config.on_shutdown` decides
+/// what happens to it. Only `Finish` keeps going, the others stop at the end of the current step.
+pub fn process_claimed_field(
+    config: &Config,
+    client: &ApiClient,
+    shutdown: &ShutdownSignal,
+    mode: Mode,
+    claim_data: FieldClaim,
+    progress: Option<PartialProgress>,
+) -> RunOutcome {
+    // don't trust the server blindly
+    if let Err(reason) = claim_data.validate(config.high_bases, &config.powers) {
+        error!(id = claim_data.id, %reason, "skipping invalid field");
+        client.block_on(release_field(config, client, &mode, &claim_data));
+        return RunOutcome::Rejected {
+            id: claim_data.id,
+            reason,
+        };
+    }
+    let before = Instant::now();
+
+    // only the rest of a resumed field still needs processing
+    let (done, start) = match progress {
+        Some((submit_data, next)) => (Some(submit_data), next),
+        None => (None, claim_data.search_start.clone()),
+    };
+    let rest = get_sub_claim(&claim_data, &start, &claim_data.search_end);
+
+    // unless we'd finish the field anyway, process it a step at a time and stop once asked to
+    if shutdown.is_triggered() && config.on_shutdown != ShutdownAction::Finish {
+        let progress = done.map(|submit_data| (submit_data, start));
+        return interrupt_field(
+            config,
+            client,
+            &mode,
+            &claim_data,
+            progress,
+            before.elapsed(),
         );
     }
-    if !benchmark {
-        submit_field_to_server(&mode, &api_base, submit_data)
+    let progress = match config.on_shutdown {
+        ShutdownAction::Finish => FieldProgress::Complete(process_field(
+            &mode,
+            config.high_bases,
+            config.parallel,
+            config.grain_size,
+            &config.near_misses,
+            &config.powers,
+            &rest,
+        )),
+        _ => process_field_until(
+            config,
+            &mode,
+            &rest,
+            &StopCondition::token(shutdown.clone()),
+        ),
+    };
+    let progress = match (done, progress) {
+        (Some(done), FieldProgress::Complete(submit_data)) => {
+            FieldProgress::Complete(merge_field_submits(done, submit_data, &config.near_misses))
+        }
+        (Some(done), FieldProgress::Partial { submit_data, next }) => FieldProgress::Partial {
+            submit_data: merge_field_submits(done, submit_data, &config.near_misses),
+            next,
+        },
+        (None, progress) => progress,
+    };
+    let submit_data = match progress {
+        // the last step may have finished the field after we were asked to stop
+        FieldProgress::Complete(_)
+            if shutdown.is_triggered()
+                && matches!(
+                    config.on_shutdown,
+                    ShutdownAction::Checkpoint | ShutdownAction::Release
+                ) =>
+        {
+            return interrupt_field(config, client, &mode, &claim_data, None, before.elapsed());
+        }
+        FieldProgress::Complete(submit_data) => submit_data,
+        FieldProgress::Partial { submit_data, next } => {
+            return interrupt_field(
+                config,
+                client,
+                &mode,
+                &claim_data,
+                Some((submit_data, next)),
+                before.elapsed(),
+            );
+        }
+    };
+
+    debug!("{:?}", submit_data);
+    let elapsed = before.elapsed();
+    info!(
+        id = claim_data.id,
+        elapsed = ?elapsed,
+        hash_rate = %format!(
+            "{:.3e}",
+            f64::try_from(&claim_data.search_range).unwrap() / elapsed.as_secs_f64()
+        ),
+        near_misses = submit_data.near_misses.as_ref().map_or(0, |n| n.len()),
+        nice_numbers = submit_data.nice_list.as_ref().map_or(0, |n| n.len()),
+        best = ?submit_data.top_near_misses.as_ref().and_then(|t| t.first()),
+        "processed field"
+    );
+    for (num, detail) in submit_data.near_miss_details.iter().flatten() {
+        info!(id = claim_data.id, %num, %detail, "near miss");
+    }
+
+    // double-check before anything gets sent
+    if config.verify {
+        if let Err(reason) = verify_field(
+            &mode,
+            &claim_data,
+            &submit_data,
+            &config.near_misses.cutoff,
+            &config.powers,
+            config.verify_sample,
+        ) {
+            error!(id = claim_data.id, %reason, "verification failed");
+            client.block_on(release_field(config, client, &mode, &claim_data));
+            return RunOutcome::Rejected {
+                id: claim_data.id,
+                reason,
+            };
+        }
+    }
+    let result = FieldResult {
+        mode,
+        claim_data,
+        submit_data,
+        elapsed,
+    };
+    config.metrics.record_result(&result);
+    RunOutcome::Completed(Box::new(result))
+}
+
+/// Submit the results of a processed field and save them to the results file,
+/// unless we're benchmarking.
+pub async fn submit_field(config: &Config, client: &ApiClient, result: &FieldResult) {
+    if config.benchmark {
+        return;
+    }
+    if let Err(e) = client.submit_field(&result.mode, &result.submit_data).await {
+        config.metrics.record_submit_failure();
+        panic!("{}", e);
+    }
+
+    save_result(config, result);
+}
+
+/// Append the results of a submitted field to the results file, if there is one.
+/// The server has them, so losing the local copy isn't worth failing over.
+fn save_result(config: &Config, result: &FieldResult) {
+    if let Some(path) = &config.results_file {
+        let engine = get_engine_name(config.high_bases, config.parallel, result.claim_data.base);
+        if let Err(e) = append_record(path, &FieldRecord::new(result.clone(), engine)) {
+            error!(id = result.claim_data.id, error = %e, "could not save results");
+        }
+    }
+}
+
+/// Release a claimed field we won't be processing, unless we're benchmarking.
+pub async fn release_field(
+    config: &Config,
+    client: &ApiClient,
+    mode: &Mode,
+    claim_data: &FieldClaim,
+) {
+    if !config.benchmark {
+        client.release_field(mode, claim_data).await
+    }
+}
+
+/// Run the program following the specified flow: claim, process, and submit one field.
+/// If the shutdown signal is triggered mid-field, `config.on_shutdown` decides what happens to it.
+pub fn run(config: &Config, client: &ApiClient, shutdown: &ShutdownSignal) -> RunOutcome {
+    let (mode, claim_data, progress) = client.block_on(claim_field(config, client));
+    let outcome = process_claimed_field(config, client, shutdown, mode, claim_data, progress);
+    if let RunOutcome::Completed(result) = &outcome {
+        client.block_on(submit_field(config, client, result));
     }
+    outcome
 }
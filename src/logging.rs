@@ -0,0 +1,85 @@
+//! A module for setting up log output.
+//! Events are tagged with their module as the target, e.g. `nice_rust::api_common`,
+//! and `RUST_LOG` can be used to override the level per module.
+
+use super::*;
+use std::io::IsTerminal;
+
+/// How each log line is formatted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// How often the log file is rotated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+/// Options for where and how to log.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub level: Level,
+    pub format: LogFormat,
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+}
+
+/// Install the global logger. Keep the returned guard alive until exit so file logs are flushed.
+pub fn init_logging(options: &LogOptions) -> Result<Option<WorkerGuard>, String> {
+    // only our own events by default, dependencies are noisy
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("nice_rust={},warn", options.level)));
+
+    let (writer, guard) = match &options.file {
+        Some(path) => {
+            let directory = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let file_name = path
+                .file_name()
+                .ok_or(format!("Invalid log file {}", path.display()))?;
+            let appender = match options.rotation {
+                LogRotation::Never => tracing_appender::rolling::never(directory, file_name),
+                LogRotation::Hourly => tracing_appender::rolling::hourly(directory, file_name),
+                LogRotation::Daily => tracing_appender::rolling::daily(directory, file_name),
+            };
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(io::stdout), None),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        // colours only make sense on a terminal, not in files, pipes or the journal
+        .with_ansi(options.file.is_none() && io::stdout().is_terminal());
+    let result = match options.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+    result.map_err(|e| format!("Error setting up logging: {}", e))?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_logging_bad_file() {
+        let options = LogOptions {
+            level: Level::INFO,
+            format: LogFormat::Json,
+            file: Some(PathBuf::from("/")),
+            rotation: LogRotation::Daily,
+        };
+        assert!(init_logging(&options).is_err());
+    }
+}
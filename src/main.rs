@@ -2,13 +2,25 @@
 
 extern crate nice_rust;
 
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
 extern crate clap;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+extern crate serde_json;
+extern crate tracing;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The checkout mode to use
     #[arg(value_enum, default_value = "detailed")]
     mode: nice_rust::Mode,
@@ -17,18 +29,52 @@ pub struct Cli {
     #[arg(long, default_value = "https://nicenumbers.net/api")]
     api_base: String,
 
+    /// How long to wait for a connection to the server
+    #[arg(long, default_value = "10s", value_parser = nice_rust::parse_duration)]
+    connect_timeout: Duration,
+
+    /// How long to wait for each request to the server to complete
+    #[arg(long, default_value = "60s", value_parser = nice_rust::parse_duration)]
+    timeout: Duration,
+
+    /// Send all requests through this proxy, e.g. http://proxy:8080 or socks5://proxy:1080
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Trust an additional root certificate (PEM), can be repeated
+    #[arg(long)]
+    ca_cert: Vec<PathBuf>,
+
     /// The username to send alongside your contribution
     #[arg(short, long, default_value = "anonymous")]
     username: String,
 
-    /// Suppress some output
-    #[arg(short, long)]
+    /// Serve Prometheus metrics at http://<ADDR>/metrics, e.g. 127.0.0.1:9100
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// Suppress some output, only log warnings and errors
+    #[arg(short, long, conflicts_with = "verbose", verbatim_doc_comment)]
     quiet: bool,
 
-    /// Show additional output
-    #[arg(short, long)]
+    /// Show additional output, including debug logs
+    /// RUST_LOG overrides this, e.g. RUST_LOG=nice_rust::api_common=trace
+    #[arg(short, long, verbatim_doc_comment)]
     verbose: bool,
 
+    /// How to format log lines
+    #[arg(long, value_enum, default_value = "text")]
+    log_format: nice_rust::LogFormat,
+
+    /// Write logs to this file instead of stdout
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// How often to start a new log file when using --log-file
+    /// Rotated files get the date and hour appended to their name
+    #[arg(long, value_enum, default_value = "never", verbatim_doc_comment)]
+    log_rotation: nice_rust::LogRotation,
+
     /// Run an offline benchmark [default: base 40, range 100000]
     #[arg(long)]
     benchmark: bool,
@@ -37,15 +83,57 @@ pub struct Cli {
     #[arg(long)]
     repeat: bool,
 
+    /// Stop repeating after this many fields have been completed
+    #[arg(long, requires = "repeat")]
+    max_fields: Option<u32>,
+
+    /// Stop claiming new fields after this long, e.g. 90s, 30m, 12h or 7d
+    #[arg(long, requires = "repeat", value_parser = nice_rust::parse_duration)]
+    max_duration: Option<Duration>,
+
+    /// Claim this many fields ahead and submit in the background while repeating
+    /// Helps keep the CPU busy when fields are small [default: 0, disabled]
+    #[arg(long, requires = "repeat", verbatim_doc_comment)]
+    pipeline_depth: Option<usize>,
+
+    /// Skip prefetched claims that won't be finished within this long of being claimed
+    #[arg(long, default_value = "1h", value_parser = nice_rust::parse_duration)]
+    claim_expiry: Duration,
+
     /// Process the range in parallel, improving speed
     #[arg(long)]
     parallel: bool,
 
+    /// How many numbers each thread processes at a time with --parallel in detailed mode
+    #[arg(long, requires = "parallel", default_value_t = nice_rust::process_integer::DEFAULT_GRAIN_SIZE)]
+    grain_size: usize,
+
     /// Enable experminetal support for inputs above 2^128
-    /// This allows acces to bases above 97 but is slower and incompatible with --parallel
+    /// This allows acces to bases 98 through 200, searched on a single thread whatever --parallel says
     #[arg(long, verbatim_doc_comment)]
     high_bases: bool,
 
+    /// Record numbers with more unique digits than this as near misses in benchmarks
+    /// Either a count like 36, or a fraction of the base like 0.85 or 17/20 [default: 0.9]
+    #[arg(long, requires = "benchmark", verbatim_doc_comment)]
+    near_miss_cutoff: Option<nice_rust::NearMissCutoff>,
+
+    /// Record the K numbers with the most unique digits as near misses in benchmarks
+    #[arg(long, requires = "benchmark", conflicts_with = "near_miss_cutoff")]
+    near_miss_top_k: Option<usize>,
+
+    /// Also report the K numbers with the most unique digits in each detailed field
+    #[arg(long)]
+    top_near_misses: Option<usize>,
+
+    /// Also report which digits are missing or repeated for each reported near miss
+    #[arg(long)]
+    near_miss_details: bool,
+
+    /// Search other powers than the square and cube in benchmarks, e.g. 2,4 for n^2 and n^4
+    #[arg(long, requires = "benchmark", default_value = "2,3")]
+    powers: nice_rust::PowerSet,
+
     /// Request a range in a specific base
     /// The server may deny this request based on capacity
     #[arg(short, long, verbatim_doc_comment)]
@@ -60,41 +148,246 @@ pub struct Cli {
     /// The same username must be used to reclaim a field
     #[arg(long, verbatim_doc_comment)]
     field: Option<u32>,
+
+    /// Double-check results with a slow independent method before submitting
+    /// Mismatched fields are released instead of submitted
+    #[arg(long, verbatim_doc_comment)]
+    verify: bool,
+
+    /// How many random numbers from each field to re-check when using --verify
+    #[arg(long, default_value = "1000")]
+    verify_sample: u32,
+
+    /// What to do with the current field on SIGINT/SIGTERM
+    /// A second signal always exits immediately
+    #[arg(long, value_enum, default_value = "finish", verbatim_doc_comment)]
+    on_shutdown: nice_rust::ShutdownAction,
+
+    /// Where to save an interrupted field when using --on-shutdown checkpoint
+    /// It will be reclaimed automatically on the next start
+    #[arg(long, default_value = "nice-checkpoint.json", verbatim_doc_comment)]
+    checkpoint_file: PathBuf,
+
+    /// Keep a record of every submitted field in this file, e.g. nice-results.jsonl
+    #[arg(long)]
+    results_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show fields recorded in the results file
+    History(HistoryArgs),
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    /// The results file to read
+    #[arg(long)]
+    results_file: PathBuf,
+
+    /// Only show fields in this base
+    #[arg(short, long)]
+    base: Option<u32>,
+
+    /// Show discoveries totaled per base instead of each field
+    #[arg(long)]
+    totals: bool,
+
+    /// How to print each field, json exports the full records
+    #[arg(long, value_enum, default_value = "text")]
+    format: HistoryFormat,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum HistoryFormat {
+    Text,
+    Json,
+}
+
+/// Print the recorded fields, or their totals per base.
+fn history(args: &HistoryArgs) {
+    let (records, warning) = nice_rust::read_records(&args.results_file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    if let Some(warning) = warning {
+        eprintln!("{}", warning);
+    }
+    let records: Vec<_> = records
+        .into_iter()
+        .filter(|record| args.base.is_none_or(|b| record.result.claim_data.base == b))
+        .collect();
+
+    if args.totals {
+        println!(
+            "{:>5} {:>7} {:>12} {:>12} {:>12}",
+            "base", "fields", "checked", "near misses", "nice"
+        );
+        for (base, totals) in nice_rust::get_base_totals(&records) {
+            println!(
+                "{:>5} {:>7} {:>12.3e} {:>12} {:>12}",
+                base,
+                totals.fields,
+                f64::try_from(&totals.numbers_checked).unwrap(),
+                totals.near_misses,
+                totals.nice_numbers
+            );
+        }
+        return;
+    }
+    match args.format {
+        HistoryFormat::Text => {
+            println!(
+                "{:>9} {:>5} {:>9} {:>12} {:>10} {:>12} {:>5}  engine",
+                "id", "base", "mode", "range", "elapsed", "near misses", "nice"
+            );
+            for record in &records {
+                let result = &record.result;
+                println!(
+                    "{:>9} {:>5} {:>9} {:>12} {:>10.3?} {:>12} {:>5}  {}",
+                    result.claim_data.id,
+                    result.claim_data.base,
+                    format!("{:?}", result.mode).to_lowercase(),
+                    result.claim_data.search_range.to_string(),
+                    result.elapsed,
+                    result
+                        .submit_data
+                        .near_misses
+                        .as_ref()
+                        .map_or(0, |n| n.len()),
+                    result.nice_numbers().len(),
+                    record.engine
+                );
+                let details = result.submit_data.near_miss_details.iter().flatten();
+                for (num, detail) in details {
+                    println!("{:>9} {}: {}", "", num, detail);
+                }
+            }
+        }
+        HistoryFormat::Json => {
+            for record in &records {
+                println!("{}", serde_json::to_string(record).unwrap());
+            }
+        }
+    }
 }
 
 fn main() {
     // parse args from command line
     let cli = Cli::parse();
-
-    // loop if repeat is set
-    while cli.repeat {
-        nice_rust::run(
-            cli.mode,
-            cli.api_base.clone(),
-            cli.username.clone(),
-            cli.quiet,
-            cli.verbose,
-            cli.benchmark,
-            cli.parallel,
-            cli.high_bases,
-            cli.base,
-            cli.range,
-            cli.field,
-        );
+    if let Some(Command::History(args)) = &cli.command {
+        history(args);
+        return;
+    }
+    let config = nice_rust::Config {
+        mode: cli.mode,
+        username: cli.username,
+        benchmark: cli.benchmark,
+        parallel: cli.parallel,
+        grain_size: cli.grain_size,
+        high_bases: cli.high_bases,
+        near_misses: nice_rust::NearMissOptions {
+            cutoff: match (cli.near_miss_cutoff, cli.near_miss_top_k) {
+                (_, Some(k)) => nice_rust::NearMissCutoff::TopK(k),
+                (Some(cutoff), None) => cutoff,
+                (None, None) => nice_rust::NearMissCutoff::default(),
+            },
+            top_k: cli.top_near_misses,
+            details: cli.near_miss_details,
+        },
+        powers: cli.powers,
+        base: cli.base,
+        range: cli.range,
+        field: cli.field,
+        on_shutdown: cli.on_shutdown,
+        checkpoint_file: cli.checkpoint_file,
+        verify: cli.verify,
+        verify_sample: cli.verify_sample,
+        metrics: nice_rust::Metrics::default(),
+        results_file: cli.results_file,
+    };
+    let _log_guard = nice_rust::init_logging(&nice_rust::LogOptions {
+        level: match (cli.quiet, cli.verbose) {
+            (true, _) => tracing::Level::WARN,
+            (_, true) => tracing::Level::DEBUG,
+            _ => tracing::Level::INFO,
+        },
+        format: cli.log_format,
+        file: cli.log_file,
+        rotation: cli.log_rotation,
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    if let Some(addr) = &cli.metrics_addr {
+        nice_rust::serve_metrics(addr, config.metrics.clone()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        });
     }
+    let client = nice_rust::ApiClient::new(&nice_rust::ApiOptions {
+        api_base: cli.api_base,
+        connect_timeout: cli.connect_timeout,
+        timeout: cli.timeout,
+        proxy: cli.proxy,
+        ca_certs: cli.ca_cert,
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let shutdown = nice_rust::ShutdownSignal::install();
 
-    // or just run once
-    nice_rust::run(
-        cli.mode,
-        cli.api_base,
-        cli.username,
-        cli.quiet,
-        cli.verbose,
-        cli.benchmark,
-        cli.parallel,
-        cli.high_bases,
-        cli.base,
-        cli.range,
-        cli.field,
-    );
+    // run once, or loop with error recovery if repeat is set
+    let limits = match cli.repeat {
+        false => nice_rust::SessionLimits {
+            max_fields: Some(1),
+            max_duration: None,
+            max_consecutive_failures: Some(1),
+        },
+        true => nice_rust::SessionLimits {
+            max_fields: cli.max_fields,
+            max_duration: cli.max_duration,
+            max_consecutive_failures: None,
+        },
+    };
+    let summary = match cli.pipeline_depth {
+        Some(depth) if depth > 0 => {
+            let pipeline = nice_rust::PipelineOptions {
+                depth,
+                claim_expiry: cli.claim_expiry,
+            };
+            nice_rust::run_pipelined_session(&config, &client, &limits, &pipeline, &shutdown)
+        }
+        _ => nice_rust::run_session(&config, &client, &limits, &shutdown),
+    };
+
+    // summarize what happened to the last field if we were interrupted
+    if shutdown.is_triggered() {
+        match &summary.last_outcome {
+            Some(nice_rust::RunOutcome::Checkpointed { id }) => println!(
+                "Field {} was saved to {}.",
+                id,
+                config.checkpoint_file.display()
+            ),
+            Some(nice_rust::RunOutcome::Released { id }) => {
+                println!("Field {} was released to the server.", id)
+            }
+            Some(nice_rust::RunOutcome::PartiallySubmitted(result)) => println!(
+                "Field {} was submitted up to {} and the rest released to the server.",
+                result.claim_data.id, result.claim_data.search_end
+            ),
+            Some(nice_rust::RunOutcome::Abandoned { id }) => {
+                println!("Field {} was abandoned.", id)
+            }
+            _ => {}
+        }
+    }
+    if cli.repeat || shutdown.is_triggered() {
+        println!("{}", summary);
+    }
+    if !cli.repeat && summary.fields_failed > 0 {
+        process::exit(1);
+    }
 }
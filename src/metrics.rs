@@ -0,0 +1,236 @@
+//! A module for exposing client statistics to Prometheus.
+//! The endpoint is served by a tiny blocking HTTP server on its own thread,
+//! since we only ever need to answer `GET /metrics` from a scraper every few seconds.
+
+use super::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::AtomicU64;
+use std::sync::Mutex;
+
+/// Counters and gauges for the running client.
+/// Cloning is cheap and every clone updates the same values.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<MetricsInner>);
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    fields_completed: AtomicU64,
+    numbers_processed: AtomicU64,
+    hash_rate: AtomicU64, // f64 bits
+    near_misses: AtomicU64,
+    nice_numbers: AtomicU64,
+    submit_failures: AtomicU64,
+    current_base: AtomicU32,
+    claimed_at: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    /// Note a freshly claimed field.
+    pub fn record_claim(&self, claim_data: &FieldClaim) {
+        self.0
+            .current_base
+            .store(claim_data.base, Ordering::Relaxed);
+        *self.0.claimed_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Add a processed field to the totals. The part of a partially submitted field
+    /// counts towards the numbers and finds, but not the fields completed.
+    pub fn record_result(&self, result: &FieldResult) {
+        let inner = &self.0;
+        let range = &result.claim_data.search_range;
+        if result.submit_data.processed_end.is_none() {
+            inner.fields_completed.fetch_add(1, Ordering::Relaxed);
+        }
+        inner
+            .numbers_processed
+            .fetch_add(u64::try_from(range).unwrap_or(u64::MAX), Ordering::Relaxed);
+        let hash_rate = f64::try_from(range).unwrap_or(f64::MAX) / result.elapsed.as_secs_f64();
+        inner
+            .hash_rate
+            .store(hash_rate.to_bits(), Ordering::Relaxed);
+        if let Some(near_misses) = &result.submit_data.near_misses {
+            inner
+                .near_misses
+                .fetch_add(near_misses.len() as u64, Ordering::Relaxed);
+        }
+        inner
+            .nice_numbers
+            .fetch_add(result.nice_numbers().len() as u64, Ordering::Relaxed);
+    }
+
+    /// Note a submission the server didn't accept.
+    pub fn record_submit_failure(&self) {
+        self.0.submit_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let inner = &self.0;
+        let claim_age = inner
+            .claimed_at
+            .lock()
+            .unwrap()
+            .map_or(0.0, |claimed_at| claimed_at.elapsed().as_secs_f64());
+        let hash_rate = f64::from_bits(inner.hash_rate.load(Ordering::Relaxed));
+        let metrics: [(&str, &str, &str, String); 8] = [
+            (
+                "nice_fields_completed_total",
+                "counter",
+                "Fields fully processed.",
+                inner.fields_completed.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_numbers_processed_total",
+                "counter",
+                "Numbers checked across all fields.",
+                inner.numbers_processed.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_hash_rate",
+                "gauge",
+                "Numbers checked per second in the last field.",
+                hash_rate.to_string(),
+            ),
+            (
+                "nice_near_misses_total",
+                "counter",
+                "Near misses found.",
+                inner.near_misses.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_nice_numbers_total",
+                "counter",
+                "Nice numbers found.",
+                inner.nice_numbers.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_submit_failures_total",
+                "counter",
+                "Submissions that failed.",
+                inner.submit_failures.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_current_base",
+                "gauge",
+                "Base of the most recently claimed field.",
+                inner.current_base.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "nice_claim_age_seconds",
+                "gauge",
+                "Seconds since the most recent field was claimed.",
+                claim_age.to_string(),
+            ),
+        ];
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            output += &format!(
+                "# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+                name, help, name, kind, name, value
+            );
+        }
+        output
+    }
+}
+
+/// Answer a single scrape. Anything other than `GET /metrics` gets a 404.
+fn handle_connection(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(API_DEFAULT_CONNECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, we don't need any of them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut stream = reader.into_inner();
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render(),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Serve the metrics at `http://{addr}/metrics` in the background.
+/// Returns the address actually bound, which is useful when asking for port 0.
+pub fn serve_metrics(addr: &str, metrics: Metrics) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| format!("Error binding metrics address {}: {}", addr, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Error binding metrics address {}: {}", addr, e))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle_connection(stream, &metrics));
+            if let Err(e) = result {
+                debug!(error = %e, "metrics request failed");
+            }
+        }
+    });
+    info!(addr = %local_addr, "serving metrics");
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve_metrics() {
+        let metrics = Metrics::default();
+        let addr = serve_metrics("127.0.0.1:0", metrics.clone()).unwrap();
+
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        metrics.record_claim(&claim_data);
+        let submit_data = process_field(
+            &Mode::Niceonly,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &NearMissOptions::default(),
+            &PowerSet::SQUARE_CUBE,
+            &claim_data,
+        );
+        metrics.record_result(&FieldResult {
+            mode: Mode::Niceonly,
+            claim_data,
+            submit_data,
+            elapsed: Duration::from_millis(10),
+        });
+        metrics.record_submit_failure();
+
+        let client = reqwest::blocking::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = response.text().unwrap();
+        assert!(body.contains("# TYPE nice_fields_completed_total counter\n"));
+        assert!(body.contains("\nnice_fields_completed_total 1\n"));
+        assert!(body.contains("\nnice_numbers_processed_total 53\n"));
+        assert!(body.contains("\nnice_hash_rate 5300\n"));
+        assert!(body.contains("\nnice_nice_numbers_total 1\n"));
+        assert!(body.contains("\nnice_submit_failures_total 1\n"));
+        assert!(body.contains("\nnice_current_base 10\n"));
+
+        let response = client.get(format!("http://{}/other", addr)).send().unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
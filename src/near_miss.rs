@@ -0,0 +1,269 @@
+//! A module for deciding which numbers get recorded as near misses.
+
+use super::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Which numbers in a field are recorded as near misses.
+/// The server expects the default, anything else is for local runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NearMissCutoff {
+    /// Numbers with more than this many unique digits.
+    Absolute(u32),
+    /// Numbers with more than this fraction of the base in unique digits, rounded down.
+    /// For example `Fraction(9, 10)` records 37 or more unique digits in base 40.
+    Fraction(u32, u32),
+    /// The K numbers with the most unique digits in each field, ties going to the smaller number.
+    TopK(usize),
+}
+
+impl Default for NearMissCutoff {
+    fn default() -> Self {
+        NearMissCutoff::Fraction(9, 10)
+    }
+}
+
+impl NearMissCutoff {
+    /// Get the threshold for a base: numbers with more unique digits than this are near misses.
+    /// Top-K has no fixed threshold, so every number passes.
+    pub fn get_threshold(&self, base: u32) -> u32 {
+        match *self {
+            NearMissCutoff::Absolute(cutoff) => cutoff,
+            NearMissCutoff::Fraction(numerator, denominator) => {
+                (base as u64 * numerator as u64 / denominator as u64) as u32
+            }
+            NearMissCutoff::TopK(_) => 0,
+        }
+    }
+}
+
+impl FromStr for NearMissCutoff {
+    type Err = String;
+
+    /// Parse an absolute count (`36`), a decimal fraction (`0.9`) or a ratio (`9/10`).
+    /// Decimals are read exactly, so `0.9` is 9/10 and not the nearest float.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid near miss cutoff {}", s);
+        let parse = |n: &str| n.parse::<u32>().map_err(|_| invalid());
+        let (numerator, denominator) = if let Some((numerator, denominator)) = s.split_once('/') {
+            (parse(numerator)?, parse(denominator)?)
+        } else if let Some((whole, decimals)) = s.split_once('.') {
+            let denominator = 10_u32
+                .checked_pow(decimals.len() as u32)
+                .ok_or_else(invalid)?;
+            let whole = if whole.is_empty() { 0 } else { parse(whole)? };
+            let decimals = parse(decimals)?;
+            let numerator = whole
+                .checked_mul(denominator)
+                .and_then(|n| n.checked_add(decimals))
+                .ok_or_else(invalid)?;
+            (numerator, denominator)
+        } else {
+            return Ok(NearMissCutoff::Absolute(parse(s)?));
+        };
+        if denominator == 0 || numerator > denominator {
+            return Err(format!("Near miss cutoff {} must be between 0 and 1", s));
+        }
+        Ok(NearMissCutoff::Fraction(numerator, denominator))
+    }
+}
+
+/// Everything the detailed engines need to know about reporting near misses.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct NearMissOptions {
+    /// Which numbers go in `near_misses`.
+    pub cutoff: NearMissCutoff,
+    /// Also report this many of the best numbers in `top_near_misses`.
+    pub top_k: Option<usize>,
+    /// Also report which digits are missing or repeated in `near_miss_details`.
+    pub details: bool,
+}
+
+/// Which digits kept a near miss from being nice.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NearMissDetail {
+    /// Digits that appear in neither the square nor the cube.
+    pub missing: Vec<u32>,
+    /// Digits that appear more than once, with how many times they appear.
+    pub duplicated: Vec<(u32, u32)>,
+}
+
+impl NearMissDetail {
+    /// Build the detail from how many times each digit appears in the square and cube.
+    pub fn from_digit_counts(digit_counts: &[u16]) -> Self {
+        let digits = || (0..).zip(digit_counts.iter().map(|&count| count as u32));
+        NearMissDetail {
+            missing: digits()
+                .filter(|&(_, count)| count == 0)
+                .map(|(digit, _)| digit)
+                .collect(),
+            duplicated: digits().filter(|&(_, count)| count > 1).collect(),
+        }
+    }
+}
+
+impl fmt::Display for NearMissDetail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing {:?}, duplicated [", self.missing)?;
+        for (i, (digit, count)) in self.duplicated.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}x{}", digit, count)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A bounded collection of the best numbers seen so far, for top-K near misses.
+/// The worst of the kept numbers sits on top of the heap so it can be replaced cheaply.
+#[derive(Debug)]
+pub struct TopNearMisses<T: Ord> {
+    k: usize,
+    heap: BinaryHeap<Reverse<(u32, Reverse<T>)>>,
+}
+
+impl<T: Ord> TopNearMisses<T> {
+    pub fn new(k: usize) -> Self {
+        TopNearMisses {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    /// Offer a number, keeping it only if it's among the best K so far.
+    pub fn push(&mut self, num: T, uniques: u32) {
+        let entry = Reverse((uniques, Reverse(num)));
+        if self.heap.len() < self.k {
+            self.heap.push(entry);
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if entry < *worst {
+                *worst = entry;
+            }
+        }
+    }
+
+    /// Whether a number with this many uniques could still make the cut.
+    pub fn accepts(&self, uniques: u32) -> bool {
+        self.heap.len() < self.k || self.heap.peek().is_some_and(|w| (w.0).0 <= uniques)
+    }
+
+    /// Combine the best of two collections, e.g. from two halves of a parallel split.
+    pub fn merge(mut self, other: Self) -> Self {
+        if self.heap.len() < other.heap.len() {
+            return other.merge(self);
+        }
+        for Reverse((uniques, Reverse(num))) in other.heap {
+            self.push(num, uniques);
+        }
+        self
+    }
+
+    /// The kept numbers with their unique digit counts.
+    pub fn into_vec(self) -> Vec<(T, u32)> {
+        self.heap
+            .into_iter()
+            .map(|Reverse((uniques, Reverse(num)))| (num, uniques))
+            .collect()
+    }
+
+    /// The kept numbers with their unique digit counts, best first.
+    pub fn into_sorted_vec(self) -> Vec<(T, u32)> {
+        // ascending order of the reversed entries is best first
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((uniques, Reverse(num)))| (num, uniques))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_near_miss_cutoff_threshold() {
+        let cutoff = NearMissCutoff::default();
+        for base in MIN_SUPPORTED_BASE..=MAX_SUPPORTED_BASE_HIGH {
+            assert_eq!(cutoff.get_threshold(base), base * 9 / 10);
+        }
+        assert_eq!(cutoff.get_threshold(10), 9);
+        assert_eq!(cutoff.get_threshold(40), 36);
+        assert_eq!(NearMissCutoff::Absolute(30).get_threshold(40), 30);
+        assert_eq!(NearMissCutoff::Fraction(3, 4).get_threshold(10), 7);
+        assert_eq!(NearMissCutoff::Fraction(1, 1).get_threshold(97), 97);
+    }
+
+    #[test]
+    fn test_near_miss_cutoff_from_str() {
+        assert_eq!("36".parse(), Ok(NearMissCutoff::Absolute(36)));
+        assert_eq!("0.9".parse(), Ok(NearMissCutoff::Fraction(9, 10)));
+        assert_eq!(".85".parse(), Ok(NearMissCutoff::Fraction(85, 100)));
+        assert_eq!("1.0".parse(), Ok(NearMissCutoff::Fraction(10, 10)));
+        assert_eq!("9/10".parse(), Ok(NearMissCutoff::Fraction(9, 10)));
+        assert!("1.5".parse::<NearMissCutoff>().is_err());
+        assert!("3/2".parse::<NearMissCutoff>().is_err());
+        assert!("1/0".parse::<NearMissCutoff>().is_err());
+        assert!("0.-9".parse::<NearMissCutoff>().is_err());
+        assert!("nine".parse::<NearMissCutoff>().is_err());
+        assert!("0.12345678901".parse::<NearMissCutoff>().is_err());
+    }
+
+    #[test]
+    fn test_near_miss_detail() {
+        // 47^2 = 2209, 47^3 = 103823
+        let mut digit_counts = [0_u16; 10];
+        for digit in [2, 2, 0, 9, 1, 0, 3, 8, 2, 3] {
+            digit_counts[digit] += 1;
+        }
+        let detail = NearMissDetail::from_digit_counts(&digit_counts);
+        assert_eq!(detail.missing, vec![4, 5, 6, 7]);
+        assert_eq!(detail.duplicated, vec![(0, 2), (2, 3), (3, 2)]);
+        assert_eq!(
+            detail.to_string(),
+            "missing [4, 5, 6, 7], duplicated [0x2, 2x3, 3x2]"
+        );
+
+        let detail = NearMissDetail::from_digit_counts(&[1; 10]);
+        assert!(detail.missing.is_empty() && detail.duplicated.is_empty());
+    }
+
+    #[test]
+    fn test_top_near_misses() {
+        let mut top = TopNearMisses::new(3);
+        for (num, uniques) in [(1, 5), (2, 7), (3, 5), (4, 9), (5, 7), (6, 2)] {
+            top.push(num, uniques);
+        }
+        assert!(top.accepts(7));
+        assert!(!top.accepts(6));
+        let mut kept = top.into_vec();
+        kept.sort();
+        assert_eq!(kept, vec![(2, 7), (4, 9), (5, 7)]);
+
+        let top = TopNearMisses::<u32>::new(0);
+        assert!(!top.accepts(100));
+        assert!(top.into_vec().is_empty());
+    }
+
+    #[test]
+    fn test_top_near_misses_merge() {
+        let values: Vec<(u32, u32)> = (0..100).map(|num| (num, num * 37 % 11)).collect();
+        let mut expected = values.clone();
+        expected.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        expected.truncate(7);
+
+        // however the values are split up, the merged result is the same
+        for split in [0, 1, 7, 50, 99, 100] {
+            let mut left = TopNearMisses::new(7);
+            let mut right = TopNearMisses::new(7);
+            for &(num, uniques) in &values[..split] {
+                left.push(num, uniques);
+            }
+            for &(num, uniques) in &values[split..] {
+                right.push(num, uniques);
+            }
+            assert_eq!(left.merge(right).into_sorted_vec(), expected);
+        }
+    }
+}
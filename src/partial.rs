@@ -0,0 +1,326 @@
+//! A module for processing a field a step at a time, so it can stop early and resume later.
+//! Each step is just a smaller field run through the usual engine, and the results are merged,
+//! so a field processed in pieces reports exactly what it would have in one go.
+
+use super::*;
+
+/// How many numbers each step covers, unless parallel threads need more to stay busy.
+pub const STEP_SIZE: u64 = 1 << 16;
+
+/// The most numbers a step covers, however large the grain size.
+/// Stopping is only checked between steps, so a deadline is noticed at most this many numbers late.
+pub const MAX_STEP_SIZE: u64 = 1 << 24;
+
+/// When to stop processing a field early. Either condition stops it, and the default never does.
+#[derive(Debug, Clone, Default)]
+pub struct StopCondition {
+    pub deadline: Option<Instant>,
+    pub token: Option<ShutdownSignal>,
+}
+
+impl StopCondition {
+    /// Stop once the deadline has passed.
+    pub fn deadline(deadline: Instant) -> Self {
+        StopCondition {
+            deadline: Some(deadline),
+            token: None,
+        }
+    }
+
+    /// Stop once the token has been triggered.
+    pub fn token(token: ShutdownSignal) -> Self {
+        StopCondition {
+            deadline: None,
+            token: Some(token),
+        }
+    }
+
+    /// Check whether processing should stop now.
+    pub fn is_met(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            || self
+                .token
+                .as_ref()
+                .is_some_and(ShutdownSignal::is_triggered)
+    }
+}
+
+/// How far a time-budgeted run got through a field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldProgress {
+    /// The whole field was processed.
+    Complete(FieldSubmit),
+    /// Only the numbers from the start of the field up to `next` were processed.
+    /// Resume with the rest of the field from `next`, then merge the results.
+    Partial {
+        submit_data: FieldSubmit,
+        next: Natural,
+    },
+}
+
+/// The part of a field from `start` to `end`, with the same id and owner.
+pub fn get_sub_claim(claim_data: &FieldClaim, start: &Natural, end: &Natural) -> FieldClaim {
+    FieldClaim {
+        id: claim_data.id,
+        username: claim_data.username.clone(),
+        base: claim_data.base,
+        search_start: start.clone(),
+        search_end: end.clone(),
+        search_range: end - start,
+    }
+}
+
+/// How many numbers each step covers: `STEP_SIZE`, or one grain per thread when parallel,
+/// but never more than `MAX_STEP_SIZE`.
+fn get_step_size(config: &Config) -> u64 {
+    match config.parallel {
+        false => STEP_SIZE,
+        true => (config.grain_size as u64)
+            .saturating_mul(rayon::current_num_threads() as u64)
+            .clamp(STEP_SIZE, MAX_STEP_SIZE),
+    }
+}
+
+/// Process a field with the engine selected by the config, stopping between steps once `stop` is met.
+/// At least one step is always processed, so every call makes progress.
+pub fn process_field_until(
+    config: &Config,
+    mode: &Mode,
+    claim_data: &FieldClaim,
+    stop: &StopCondition,
+) -> FieldProgress {
+    let step_size = get_step_size(config);
+    let mut start = claim_data.search_start.clone();
+    let mut submit_data: Option<FieldSubmit> = None;
+    loop {
+        let end = (&start + Natural::from(step_size)).min(claim_data.search_end.clone());
+        let step_data = process_field(
+            mode,
+            config.high_bases,
+            config.parallel,
+            config.grain_size,
+            &config.near_misses,
+            &config.powers,
+            &get_sub_claim(claim_data, &start, &end),
+        );
+        let merged = match submit_data {
+            Some(submit_data) => merge_field_submits(submit_data, step_data, &config.near_misses),
+            None => step_data,
+        };
+        if end == claim_data.search_end {
+            return FieldProgress::Complete(merged);
+        }
+        if stop.is_met() {
+            debug!(id = claim_data.id, next = %end, "stopping field early");
+            return FieldProgress::Partial {
+                submit_data: merged,
+                next: end,
+            };
+        }
+        submit_data = Some(merged);
+        start = end;
+    }
+}
+
+/// Keep the best K of two lists of numbers and their unique digit counts, best first.
+fn merge_top(
+    first: Vec<(String, u32)>,
+    second: Vec<(String, u32)>,
+    k: usize,
+) -> Vec<(String, u32)> {
+    let mut top = TopNearMisses::new(k);
+    for (num, uniques) in first.into_iter().chain(second) {
+        top.push(Natural::from_str(&num).unwrap(), uniques);
+    }
+    top.into_sorted_vec()
+        .into_iter()
+        .map(|(num, uniques)| (num.to_string(), uniques))
+        .collect()
+}
+
+/// Combine the results of two adjacent parts of a field, in order, into the results for both.
+/// The near miss options must be the ones both parts were processed with.
+pub fn merge_field_submits(
+    first: FieldSubmit,
+    second: FieldSubmit,
+    near_miss_options: &NearMissOptions,
+) -> FieldSubmit {
+    // add up the histograms
+    let unique_count = match (first.unique_count, second.unique_count) {
+        (Some(mut unique_count), Some(other)) => {
+            for (uniques, count) in other {
+                *unique_count.entry(uniques).or_insert(0) += count;
+            }
+            Some(unique_count)
+        }
+        (unique_count, other) => unique_count.or(other),
+    };
+
+    // near misses are everything over the cutoff, or just the best K of both
+    let near_misses = match (first.near_misses, second.near_misses) {
+        (Some(near_misses), Some(other)) => Some(match near_miss_options.cutoff {
+            NearMissCutoff::TopK(k) => merge_top(
+                near_misses.into_iter().collect(),
+                other.into_iter().collect(),
+                k,
+            )
+            .into_iter()
+            .collect(),
+            _ => near_misses.into_iter().chain(other).collect(),
+        }),
+        (near_misses, other) => near_misses.or(other),
+    };
+
+    // nice numbers from the first part come first
+    let nice_list = match (first.nice_list, second.nice_list) {
+        (Some(mut nice_list), Some(other)) => {
+            nice_list.extend(other);
+            Some(nice_list)
+        }
+        (nice_list, other) => nice_list.or(other),
+    };
+
+    let top_near_misses = match (first.top_near_misses, second.top_near_misses) {
+        (Some(top), Some(other)) => {
+            let k = near_miss_options
+                .top_k
+                .unwrap_or(top.len().max(other.len()));
+            Some(merge_top(top, other, k))
+        }
+        (top, other) => top.or(other),
+    };
+
+    // only keep details for numbers that are still reported
+    let near_miss_details = match (first.near_miss_details, second.near_miss_details) {
+        (Some(details), Some(other)) => Some(
+            details
+                .into_iter()
+                .chain(other)
+                .filter(|(num, _)| {
+                    near_misses.as_ref().is_some_and(|n| n.contains_key(num))
+                        || top_near_misses
+                            .iter()
+                            .flatten()
+                            .any(|(top_num, _)| top_num == num)
+                })
+                .collect(),
+        ),
+        (details, other) => details.or(other),
+    };
+
+    FieldSubmit {
+        id: first.id,
+        username: first.username,
+        client_version: first.client_version,
+        unique_count,
+        near_misses,
+        nice_list,
+        top_near_misses,
+        near_miss_details,
+        processed_end: second.processed_end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config for a benchmark run with every kind of near miss reporting turned on.
+    fn get_config(cutoff: NearMissCutoff) -> Config {
+        Config {
+            benchmark: true,
+            near_misses: NearMissOptions {
+                cutoff,
+                top_k: Some(5),
+                details: true,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_step_size() {
+        assert_eq!(get_step_size(&Config::default()), STEP_SIZE);
+        for grain_size in [
+            0,
+            1,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            1 << 40,
+            usize::MAX,
+        ] {
+            let config = Config {
+                parallel: true,
+                grain_size,
+                ..Default::default()
+            };
+            let step_size = get_step_size(&config);
+            assert!((STEP_SIZE..=MAX_STEP_SIZE).contains(&step_size));
+        }
+    }
+
+    #[test]
+    fn test_process_field_until_complete() {
+        let config = get_config(NearMissCutoff::default());
+        let claim_data = get_field_benchmark(Some(10), None, &config.powers);
+        for mode in [Mode::Detailed, Mode::Niceonly] {
+            assert_eq!(
+                process_field_until(&config, &mode, &claim_data, &StopCondition::default()),
+                FieldProgress::Complete(process_field(
+                    &mode,
+                    false,
+                    false,
+                    config.grain_size,
+                    &config.near_misses,
+                    &config.powers,
+                    &claim_data
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_field_until_resume() {
+        let claim_data = get_field_benchmark(Some(40), Some(150000), &PowerSet::SQUARE_CUBE);
+        let token = ShutdownSignal::new();
+        token.trigger();
+        for cutoff in [NearMissCutoff::Fraction(3, 4), NearMissCutoff::TopK(5)] {
+            let config = get_config(cutoff);
+            for mode in [Mode::Detailed, Mode::Niceonly] {
+                let expected = process_field(
+                    &mode,
+                    false,
+                    false,
+                    config.grain_size,
+                    &config.near_misses,
+                    &config.powers,
+                    &claim_data,
+                );
+
+                // stop after every step, alternating the kind of stop, until the field is done
+                let stops = [
+                    StopCondition::token(token.clone()),
+                    StopCondition::deadline(Instant::now()),
+                ];
+                let mut rest = claim_data.clone();
+                let mut parts = Vec::new();
+                let submit_data = loop {
+                    let stop = &stops[parts.len() % stops.len()];
+                    match process_field_until(&config, &mode, &rest, stop) {
+                        FieldProgress::Complete(submit_data) => break submit_data,
+                        FieldProgress::Partial { submit_data, next } => {
+                            assert_eq!(next, &rest.search_start + Natural::from(STEP_SIZE));
+                            rest = get_sub_claim(&claim_data, &next, &claim_data.search_end);
+                            parts.push(submit_data);
+                        }
+                    }
+                };
+                assert_eq!(parts.len(), 2);
+                let merged = parts.into_iter().rev().fold(submit_data, |rest, part| {
+                    merge_field_submits(part, rest, &config.near_misses)
+                });
+                assert_eq!(merged, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! A module for describing which powers of n have to share the digits of the base.
+//! The project searches square-cube pandigitals, but the same search works for any pair of powers.
+
+use super::*;
+
+/// A pair of exponents: a number is nice when n^low and n^high together use every digit exactly once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerSet {
+    pub low: u32,
+    pub high: u32,
+}
+
+impl PowerSet {
+    /// The square and cube, the only pair the server hands out.
+    pub const SQUARE_CUBE: PowerSet = PowerSet { low: 2, high: 3 };
+
+    /// Build a power set, checking the exponents make sense.
+    pub fn new(low: u32, high: u32) -> Result<Self, String> {
+        if low == 0 || low >= high {
+            return Err(format!(
+                "Invalid powers {},{}: need 0 < low < high",
+                low, high
+            ));
+        }
+        Ok(PowerSet { low, high })
+    }
+
+    /// Whether this is the square-cube search, which has faster special cases.
+    pub fn is_square_cube(&self) -> bool {
+        *self == PowerSet::SQUARE_CUBE
+    }
+
+    /// Raise a number to the lower power.
+    pub fn get_low_power(&self, num: &Natural) -> Natural {
+        num.pow(self.low as u64)
+    }
+
+    /// Raise a number to the higher power, reusing the lower power already computed.
+    pub fn get_high_power(&self, num: &Natural, low_power: &Natural) -> Natural {
+        match self.high - self.low {
+            1 => low_power * num,
+            gap => low_power * num.pow(gap as u64),
+        }
+    }
+
+    /// Raise a number to both powers.
+    pub fn get_powers(&self, num: &Natural) -> (Natural, Natural) {
+        let low = self.get_low_power(num);
+        let high = self.get_high_power(num, &low);
+        (low, high)
+    }
+}
+
+impl Default for PowerSet {
+    fn default() -> Self {
+        PowerSet::SQUARE_CUBE
+    }
+}
+
+impl fmt::Display for PowerSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.low, self.high)
+    }
+}
+
+impl FromStr for PowerSet {
+    type Err = String;
+
+    /// Parse a pair of exponents like `2,4`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid powers {}, expected something like 2,4", s);
+        let (low, high) = s.split_once(',').ok_or_else(invalid)?;
+        let low = low.trim().parse().map_err(|_| invalid())?;
+        let high = high.trim().parse().map_err(|_| invalid())?;
+        PowerSet::new(low, high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_set_from_str() {
+        assert_eq!("2,3".parse(), Ok(PowerSet::SQUARE_CUBE));
+        assert_eq!("3, 4".parse(), Ok(PowerSet { low: 3, high: 4 }));
+        assert_eq!(PowerSet::new(2, 4).unwrap().to_string(), "2,4");
+        assert!("4,2".parse::<PowerSet>().is_err());
+        assert!("0,2".parse::<PowerSet>().is_err());
+        assert!("3,3".parse::<PowerSet>().is_err());
+        assert!("2".parse::<PowerSet>().is_err());
+        assert!("two,three".parse::<PowerSet>().is_err());
+    }
+
+    #[test]
+    fn test_get_powers() {
+        let num = Natural::from(7_u32);
+        assert_eq!(
+            PowerSet::SQUARE_CUBE.get_powers(&num),
+            (Natural::from(49_u32), Natural::from(343_u32))
+        );
+        assert_eq!(
+            PowerSet::new(2, 4).unwrap().get_powers(&num),
+            (Natural::from(49_u32), Natural::from(2401_u32))
+        );
+    }
+}
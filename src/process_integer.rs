@@ -4,138 +4,286 @@
 
 use super::*;
 
-/// Get the count of unique digits in a number's sqube when represented in a specific base.
-pub fn get_num_uniques(num: u128, base: u32) -> u32 {
+/// Count how many times each digit appears in a number's powers when represented in a specific base.
+pub fn get_digit_counts(num: u128, context: &BaseContext) -> Vec<u16> {
     let num = Natural::from(num);
+    let (extractor, powers) = (&context.extractor, &context.powers);
 
-    // create a boolean array that represents all possible digits
-    let mut digits_indicator: Vec<bool> = vec![false; base as usize];
+    // create an array that counts all possible digits
+    let mut digit_counts: Vec<u16> = vec![0; context.base as usize];
+    let mut count_digit = |digit: u32| {
+        digit_counts[digit as usize] += 1;
+        true
+    };
 
-    // square the number, convert to base and save the digits
-    let squared = (&num).pow(2);
-    for digit in squared.to_digits_asc(&base) {
-        digits_indicator[digit as usize] = true;
+    // raise the number to the lower power (the square), convert to base and count the digits
+    let low = powers.get_low_power(&num);
+    extractor.for_each_digit(&low, &mut count_digit);
+
+    // the higher power (the cube), convert to base and count the digits
+    let high = powers.get_high_power(&num, &low);
+    extractor.for_each_digit(&high, &mut count_digit);
+    digit_counts
+}
+
+/// Get the count of unique digits in a number's powers when represented in a specific base.
+pub fn get_num_uniques(num: u128, context: &BaseContext) -> u32 {
+    if let Some(kernel) = &context.kernel {
+        return (kernel.get_num_uniques)(num);
     }
 
-    // cube, convert to base and save the digits
-    let cubed = squared * num;
-    for digit in cubed.to_digits_asc(&base) {
-        digits_indicator[digit as usize] = true;
+    // output the number of digits that showed up at all
+    get_digit_counts(num, context)
+        .iter()
+        .filter(|&&count| count > 0)
+        .count() as u32
+}
+
+/// How many numbers each parallel task covers in detailed mode, unless configured otherwise.
+/// Big enough that merging the accumulators costs nothing, small enough to balance across threads.
+pub const DEFAULT_GRAIN_SIZE: usize = 1 << 14;
+
+/// Running statistics for the part of a field one thread has processed.
+/// Nothing is kept per number, so threads never share or reallocate anything while they count.
+struct DetailedAccumulator {
+    near_misses_cutoff: u32,
+    unique_count: Vec<u32>,
+    near_misses: Vec<(u128, u32)>,
+    top_cutoff: Option<TopNearMisses<u128>>,
+    top_near_misses: Option<TopNearMisses<u128>>,
+}
+
+impl DetailedAccumulator {
+    fn new(context: &BaseContext, near_miss_options: &NearMissOptions) -> Self {
+        DetailedAccumulator {
+            near_misses_cutoff: context.near_misses_cutoff,
+            unique_count: vec![0; context.base as usize],
+            near_misses: Vec::new(),
+            top_cutoff: match near_miss_options.cutoff {
+                NearMissCutoff::TopK(k) => Some(TopNearMisses::new(k)),
+                _ => None,
+            },
+            top_near_misses: near_miss_options.top_k.map(TopNearMisses::new),
+        }
+    }
+
+    /// Record the unique digit count of one number.
+    fn push(&mut self, num: u128, uniques: u32) {
+        self.unique_count[uniques as usize - 1] += 1;
+        if let Some(top) = &mut self.top_cutoff {
+            if top.accepts(uniques) {
+                top.push(num, uniques);
+            }
+        } else if uniques > self.near_misses_cutoff {
+            self.near_misses.push((num, uniques));
+        }
+        if let Some(top) = &mut self.top_near_misses {
+            if top.accepts(uniques) {
+                top.push(num, uniques);
+            }
+        }
+    }
+
+    /// Count every number in a range.
+    fn push_range(&mut self, nums: Range<u128>, context: &BaseContext) {
+        for num in nums {
+            self.push(num, get_num_uniques(num, context));
+        }
     }
 
-    // output the number of unique digits
-    let mut unique_digits = 0;
-    for digit in digits_indicator {
-        if digit {
-            unique_digits += 1
+    /// Combine the statistics of two parts of a field.
+    fn merge(mut self, other: Self) -> Self {
+        for (count, other_count) in self.unique_count.iter_mut().zip(other.unique_count) {
+            *count += other_count;
         }
+        self.near_misses.extend(other.near_misses);
+        self.top_cutoff = match (self.top_cutoff, other.top_cutoff) {
+            (Some(top), Some(other_top)) => Some(top.merge(other_top)),
+            (top, _) => top,
+        };
+        self.top_near_misses = match (self.top_near_misses, other.top_near_misses) {
+            (Some(top), Some(other_top)) => Some(top.merge(other_top)),
+            (top, _) => top,
+        };
+        self
     }
-    return unique_digits;
 }
 
 /// Process a field by aggregating statistics on the niceness of numbers in a range.
-pub fn process_detailed(claim_data: &FieldClaim, parallel: bool) -> FieldSubmit {
-    let base = claim_data.base;
+/// With `parallel` the field is split into chunks of `grain_size` numbers,
+/// each thread folds its chunks into its own accumulator, and those are merged at the end.
+pub fn process_detailed(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+    parallel: bool,
+    grain_size: usize,
+    near_miss_options: &NearMissOptions,
+) -> FieldSubmit {
     let search_start = u128::try_from(&claim_data.search_start).unwrap();
     let search_end = u128::try_from(&claim_data.search_end).unwrap();
 
-    // process the range and collect num_uniques for each item in the range
-    let result_map: HashMap<u128, u32> = match parallel {
-        false => (search_start..search_end)
-            .into_iter()
-            .map(|num| (num, get_num_uniques(num, base)))
-            .collect(),
-        true => (search_start..search_end)
-            .into_par_iter()
-            .map(|num| (num, get_num_uniques(num, base)))
-            .collect(),
+    // process the range, keeping only the statistics we report
+    let new_accumulator = || DetailedAccumulator::new(context, near_miss_options);
+    let accumulator = match parallel {
+        false => {
+            let mut accumulator = new_accumulator();
+            accumulator.push_range(search_start..search_end, context);
+            accumulator
+        }
+        true => {
+            let grain_size = grain_size.max(1) as u128;
+            let num_chunks = (search_end - search_start).div_ceil(grain_size);
+            (0..num_chunks)
+                .into_par_iter()
+                .fold(new_accumulator, |mut accumulator, i| {
+                    let first = search_start + i * grain_size;
+                    let chunk = first..(first + grain_size).min(search_end);
+                    accumulator.push_range(chunk, context);
+                    accumulator
+                })
+                .reduce(new_accumulator, DetailedAccumulator::merge)
+        }
     };
 
-    // collect the near misses from the result map
-    let near_misses_cutoff = (base as f32 * NEAR_MISS_CUTOFF_PERCENT) as u32;
-    let near_misses: HashMap<String, u32> = result_map
-        .clone()
-        .into_iter()
-        .filter(|&(_, value)| value > near_misses_cutoff)
-        .map(|(num, value)| (num.to_string(), value))
-        .collect();
+    // the near misses are the top K if that's the cutoff
+    let near_misses = match accumulator.top_cutoff {
+        Some(top) => top.into_vec(),
+        None => accumulator.near_misses,
+    };
 
-    // collect the distribution of uniqueness across the result map
-    let unique_count: HashMap<u32, u32> = (1..=base)
-        .into_iter()
-        .map(|i| (i, result_map.values().filter(|&&v| v == i).count() as u32))
+    // the distribution of uniqueness across the range
+    let unique_count: HashMap<u32, u32> = accumulator
+        .unique_count
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| (i as u32 + 1, count))
         .collect();
 
+    // and the best of the best, if requested
+    let top_near_misses = accumulator
+        .top_near_misses
+        .map(TopNearMisses::into_sorted_vec);
+
+    // recount the digits of anything we report to see what went wrong
+    let near_miss_details = near_miss_options.details.then(|| {
+        near_misses
+            .iter()
+            .chain(top_near_misses.iter().flatten())
+            .map(|&(num, _)| {
+                let digit_counts = get_digit_counts(num, context);
+                (
+                    num.to_string(),
+                    NearMissDetail::from_digit_counts(&digit_counts),
+                )
+            })
+            .collect()
+    });
+
     return FieldSubmit {
         id: claim_data.id,
         username: claim_data.username.clone(),
         client_version: CLIENT_VERSION.to_string(),
         unique_count: Some(unique_count),
-        near_misses: Some(near_misses),
+        near_misses: Some(
+            near_misses
+                .into_iter()
+                .map(|(num, value)| (num.to_string(), value))
+                .collect(),
+        ),
         nice_list: None,
+        top_near_misses: top_near_misses.map(|top| {
+            top.into_iter()
+                .map(|(num, value)| (num.to_string(), value))
+                .collect()
+        }),
+        near_miss_details,
+        processed_end: None,
     };
 }
 
 /// Quickly determine if a number is 100% nice.
 /// Assumes we have already done residue class filtering.
-pub fn get_is_nice(num: u128, base: u32) -> bool {
+pub fn get_is_nice(num: u128, context: &BaseContext) -> bool {
+    if let Some(kernel) = &context.kernel {
+        return (kernel.get_is_nice)(num);
+    }
     let num = Natural::from(num);
-    let base_natural = Natural::from(base);
+    let (extractor, powers) = (&context.extractor, &context.powers);
 
     // create a boolean array that represents all possible digits
     let mut digits_indicator = [false; MAX_SUPPORTED_BASE_NORMAL as usize];
+    let mut check_digit = |digit: u32| {
+        let seen = digits_indicator[digit as usize];
+        digits_indicator[digit as usize] = true;
+        !seen
+    };
 
-    // square the number and check those digits
-    let squared = (&num).pow(2);
-    let mut n = squared.clone();
-    while n > 0 {
-        let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-        if digits_indicator[remainder] {
-            return false;
-        }
-        digits_indicator[remainder] = true;
-    }
-
-    // cube the number and check those digit
-    let mut n = squared * num;
-    while n > 0 {
-        let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-        if digits_indicator[remainder] {
-            return false;
-        }
-        digits_indicator[remainder] = true;
+    // square the number (or the lower power) and check those digits
+    let low = powers.get_low_power(&num);
+    if !extractor.for_each_digit(&low, &mut check_digit) {
+        return false;
     }
-    return true;
-}
 
-/// Get a list of residue filters for a base.
-/// For more information: https://beautifulthorns.wixsite.com/home/post/progress-update-on-the-search-for-nice-numbers
-pub fn get_residue_filter(base: u32) -> Vec<u32> {
-    let target_residue = base * (base - 1) / 2 % (base - 1);
-    (0..(base - 1))
-        .filter(|num| (num.pow(2) + num.pow(3)) % (base - 1) == target_residue)
-        .collect()
+    // cube the number (or the higher power) and check those digits
+    let high = powers.get_high_power(&num, &low);
+    extractor.for_each_digit(&high, &mut check_digit)
 }
 
 /// Process a field by looking for completely nice numbers.
 /// Implements several optimizations over the detailed search.
-pub fn process_niceonly(claim_data: &FieldClaim, parallel: bool) -> FieldSubmit {
+pub fn process_niceonly(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+    parallel: bool,
+) -> FieldSubmit {
     let base = claim_data.base;
     let search_start = u128::try_from(&claim_data.search_start).unwrap();
     let search_end = u128::try_from(&claim_data.search_end).unwrap();
-    let residue_filter = get_residue_filter(base);
+    debug!(
+        base,
+        residues_kept = context.residues_kept(),
+        residues_total = base - 1,
+        "residue filter"
+    );
+
+    // skip anything with repeats in the leading digits before checking number by number
+    let ranges: Vec<(u128, u128)> = get_candidate_ranges(
+        base,
+        &context.powers,
+        &claim_data.search_start,
+        &claim_data.search_end,
+        LEADING_DIGITS_LEAF_SIZE,
+    )
+    .iter()
+    .map(|(start, end)| (u128::try_from(start).unwrap(), u128::try_from(end).unwrap()))
+    .collect();
+    debug!(
+        base,
+        ranges = ranges.len(),
+        kept = ranges.iter().map(|(start, end)| end - start).sum::<u128>(),
+        total = search_end - search_start,
+        "leading digit filter"
+    );
+
+    // check residues with the kernel for this base if there is one
+    let is_residue_kept = |num: u128| match &context.kernel {
+        Some(kernel) => (kernel.is_residue_kept)(num),
+        None => context.is_residue_kept((num % (base as u128 - 1)) as u32),
+    };
 
     let nice_list = match parallel {
-        false => (search_start..search_end)
-            .into_iter()
-            .filter(|num| residue_filter.contains(&((num % (base as u128 - 1)) as u32)))
-            .filter(|num| get_is_nice(*num, base))
+        false => ranges
+            .iter()
+            .flat_map(|&(start, end)| start..end)
+            .filter(|num| is_residue_kept(*num))
+            .filter(|num| get_is_nice(*num, context))
             .map(|num| num.to_string())
             .collect(),
-        true => (search_start..search_end)
-            .into_par_iter()
-            .filter(|num| residue_filter.contains(&((num % (base as u128 - 1)) as u32)))
-            .filter(|num| get_is_nice(*num, base))
+        true => ranges
+            .par_iter()
+            .flat_map(|&(start, end)| (start..end).into_par_iter())
+            .filter(|num| is_residue_kept(*num))
+            .filter(|num| get_is_nice(*num, context))
             .map(|num| num.to_string())
             .collect(),
     };
@@ -147,6 +295,9 @@ pub fn process_niceonly(claim_data: &FieldClaim, parallel: bool) -> FieldSubmit
         unique_count: None,
         near_misses: None,
         nice_list: Some(nice_list),
+        top_near_misses: None,
+        near_miss_details: None,
+        processed_end: None,
     };
 }
 
@@ -154,6 +305,15 @@ pub fn process_niceonly(claim_data: &FieldClaim, parallel: bool) -> FieldSubmit
 mod tests {
     use super::*;
 
+    /// Precompute the square-cube context for a test field.
+    fn get_context(claim_data: &FieldClaim) -> BaseContext {
+        BaseContext::new(
+            claim_data,
+            &PowerSet::SQUARE_CUBE,
+            &NearMissCutoff::default(),
+        )
+    }
+
     #[test]
     fn process_detailed_b10() {
         let claim_data = FieldClaim {
@@ -182,8 +342,20 @@ mod tests {
             ])),
             near_misses: Some(HashMap::from([("69".to_string(), 10)])),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data, true), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                true,
+                DEFAULT_GRAIN_SIZE,
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -244,8 +416,20 @@ mod tests {
             ])),
             near_misses: Some(HashMap::new()),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data, true), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                true,
+                DEFAULT_GRAIN_SIZE,
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -346,8 +530,46 @@ mod tests {
             ])),
             near_misses: Some(HashMap::new()),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data, true), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                true,
+                DEFAULT_GRAIN_SIZE,
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
+    }
+
+    #[test]
+    fn process_detailed_grain_sizes() {
+        // the merged accumulators match a single pass however the field is split
+        let claim_data = get_field_benchmark(Some(40), Some(1000), &PowerSet::SQUARE_CUBE);
+        for options in [
+            NearMissOptions {
+                cutoff: NearMissCutoff::Fraction(3, 4),
+                top_k: Some(10),
+                details: true,
+            },
+            NearMissOptions {
+                cutoff: NearMissCutoff::TopK(10),
+                ..Default::default()
+            },
+        ] {
+            let context = BaseContext::new(&claim_data, &PowerSet::SQUARE_CUBE, &options.cutoff);
+            let expected = process_detailed(&claim_data, &context, false, 0, &options);
+            for grain_size in [0, 1, 5, 64, DEFAULT_GRAIN_SIZE] {
+                assert_eq!(
+                    process_detailed(&claim_data, &context, true, grain_size, &options),
+                    expected
+                );
+            }
+        }
     }
 
     #[test]
@@ -367,8 +589,14 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::from(["69".to_string()])),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data, true), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data), true),
+            submit_data
+        );
     }
 
     #[test]
@@ -388,8 +616,14 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::new()),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data, true), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data), true),
+            submit_data
+        );
     }
 
     #[test]
@@ -409,7 +643,13 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::new()),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data, true), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data), true),
+            submit_data
+        );
     }
 }
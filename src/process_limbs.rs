@@ -0,0 +1,351 @@
+//! A module with "nice" calculation utilities.
+//! We will iterate over n as a fixed array of u64 limbs, sized per field so n^2 and n^3 always fit.
+//! Nothing in the loop allocates, which makes it much faster than the Natural engine above base 97.
+
+use super::*;
+
+/// The most limbs any engine is compiled for, enough for every power set through base 200.
+pub const MAX_LIMBS: usize = 24;
+
+/// How many limbs the largest power in the field needs.
+pub fn get_limb_count(context: &BaseContext) -> usize {
+    // anything with this many digits is below base^digits
+    let bits = (&context.base_natural)
+        .pow(context.digit_lengths.1 as u64)
+        .significant_bits();
+    bits.div_ceil(64) as usize
+}
+
+/// Count how many times each digit appears in a number's powers, adding to `digit_counts`.
+fn count_digits<const N: usize>(
+    num: &FixedNum<N>,
+    context: &BaseContext,
+    digit_counts: &mut [u16],
+) {
+    let mut count_digit = |digit: u32| {
+        digit_counts[digit as usize] += 1;
+        true
+    };
+    let (low, high) = num.get_powers(&context.powers);
+    context
+        .extractor
+        .for_each_digit_in_limbs(&mut low.into_limbs(), &mut count_digit);
+    context
+        .extractor
+        .for_each_digit_in_limbs(&mut high.into_limbs(), &mut count_digit);
+}
+
+/// Process a field by aggregating statistics on the niceness of numbers in a range.
+pub fn process_detailed(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+    near_miss_options: &NearMissOptions,
+) -> FieldSubmit {
+    match get_limb_count(context) {
+        0..=8 => process_detailed_fixed::<8>(claim_data, context, near_miss_options),
+        9..=12 => process_detailed_fixed::<12>(claim_data, context, near_miss_options),
+        13..=16 => process_detailed_fixed::<16>(claim_data, context, near_miss_options),
+        17..=MAX_LIMBS => {
+            process_detailed_fixed::<MAX_LIMBS>(claim_data, context, near_miss_options)
+        }
+        limbs => panic!("No limb engine for {} limbs", limbs),
+    }
+}
+
+/// Process a field in detailed mode with N limbs per number.
+fn process_detailed_fixed<const N: usize>(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+    near_miss_options: &NearMissOptions,
+) -> FieldSubmit {
+    let base = claim_data.base;
+
+    // output variables
+    let mut unique_digits: u32;
+    let mut near_misses: HashMap<String, u32> = HashMap::new();
+    let mut near_miss_details: HashMap<String, NearMissDetail> = HashMap::new();
+    let mut top_cutoff = match near_miss_options.cutoff {
+        NearMissCutoff::TopK(k) => Some(TopNearMisses::new(k)),
+        _ => None,
+    };
+    let mut top_near_misses = near_miss_options.top_k.map(TopNearMisses::new);
+    let mut unique_count_vec = vec![0; base as usize];
+
+    // iterator variables
+    let mut num = FixedNum::<N>::from_natural(&claim_data.search_start).unwrap();
+    let search_end = FixedNum::<N>::from_natural(&claim_data.search_end).unwrap();
+    let mut digit_counts = [0; MAX_SUPPORTED_BASE_HIGH as usize];
+
+    while num < search_end {
+        // zero out the counts, then count the digits of the square and cube
+        digit_counts.iter_mut().for_each(|x| *x = 0);
+        count_digits(&num, context, &mut digit_counts);
+
+        // count the digits, update the unique count
+        unique_digits = digit_counts.iter().filter(|&&x| x > 0).count() as u32;
+        unique_count_vec[unique_digits as usize - 1] += 1;
+
+        // save if the number is pretty nice, converting it only then
+        if let Some(top) = &mut top_cutoff {
+            if top.accepts(unique_digits) {
+                top.push(num.to_natural(), unique_digits);
+            }
+        } else if unique_digits > context.near_misses_cutoff {
+            let num = num.to_natural().to_string();
+            if near_miss_options.details {
+                near_miss_details.insert(
+                    num.clone(),
+                    NearMissDetail::from_digit_counts(&digit_counts[..base as usize]),
+                );
+            }
+            near_misses.insert(num, unique_digits);
+        }
+        if let Some(top) = &mut top_near_misses {
+            if top.accepts(unique_digits) {
+                top.push(num.to_natural(), unique_digits);
+            }
+        }
+
+        // increment num
+        num.increment();
+    }
+
+    // the top numbers are only known at the end, so recount their digits for details
+    let top_cutoff = top_cutoff.map(TopNearMisses::into_vec);
+    let top_near_misses = top_near_misses.map(TopNearMisses::into_sorted_vec);
+    if near_miss_options.details {
+        for (num, _) in top_cutoff.iter().chain(top_near_misses.iter()).flatten() {
+            let mut digit_counts = vec![0; base as usize];
+            count_digits(
+                &FixedNum::<N>::from_natural(num).unwrap(),
+                context,
+                &mut digit_counts,
+            );
+            near_miss_details.insert(
+                num.to_string(),
+                NearMissDetail::from_digit_counts(&digit_counts),
+            );
+        }
+    }
+    if let Some(top) = top_cutoff {
+        near_misses = top
+            .into_iter()
+            .map(|(num, value)| (num.to_string(), value))
+            .collect();
+    }
+
+    // sum unique counts from vec
+    let unique_count = unique_count_vec
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| (i as u32 + 1, x))
+        .collect();
+
+    FieldSubmit {
+        id: claim_data.id,
+        username: claim_data.username.clone(),
+        client_version: CLIENT_VERSION.to_string(),
+        unique_count: Some(unique_count),
+        near_misses: Some(near_misses),
+        nice_list: None,
+        top_near_misses: top_near_misses.map(|top| {
+            top.into_iter()
+                .map(|(num, value)| (num.to_string(), value))
+                .collect()
+        }),
+        near_miss_details: near_miss_options.details.then_some(near_miss_details),
+        processed_end: None,
+    }
+}
+
+/// Process a field by looking for completely nice numbers.
+/// Implements several optimizations over the detailed search.
+pub fn process_niceonly(claim_data: &FieldClaim, context: &BaseContext) -> FieldSubmit {
+    match get_limb_count(context) {
+        0..=8 => process_niceonly_fixed::<8>(claim_data, context),
+        9..=12 => process_niceonly_fixed::<12>(claim_data, context),
+        13..=16 => process_niceonly_fixed::<16>(claim_data, context),
+        17..=MAX_LIMBS => process_niceonly_fixed::<MAX_LIMBS>(claim_data, context),
+        limbs => panic!("No limb engine for {} limbs", limbs),
+    }
+}
+
+/// Process a field in niceonly mode with N limbs per number.
+fn process_niceonly_fixed<const N: usize>(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+) -> FieldSubmit {
+    let base = claim_data.base;
+    let residue_divisor = LimbReciprocal::new(base as u64 - 1);
+    debug!(
+        base,
+        residues_kept = context.residues_kept(),
+        residues_total = base - 1,
+        "residue filter"
+    );
+
+    // skip anything with repeats in the leading digits before checking number by number
+    let ranges = get_candidate_ranges(
+        base,
+        &context.powers,
+        &claim_data.search_start,
+        &claim_data.search_end,
+        LEADING_DIGITS_LEAF_SIZE,
+    );
+    debug!(
+        base,
+        ranges = ranges.len(),
+        kept = %ranges.iter().fold(Natural::ZERO, |acc, (start, end)| acc + end - start),
+        total = %claim_data.search_range,
+        "leading digit filter"
+    );
+
+    // output & iterator variables
+    let mut nice_list = Vec::new();
+    let mut digits_indicator = [false; MAX_SUPPORTED_BASE_HIGH as usize];
+
+    for (start, end) in ranges {
+        let mut num = FixedNum::<N>::from_natural(&start).unwrap();
+        let range_end = FixedNum::<N>::from_natural(&end).unwrap();
+        while num < range_end {
+            if is_nice(&num, context, &residue_divisor, &mut digits_indicator) {
+                nice_list.push(num.to_natural().to_string());
+            }
+            num.increment();
+        }
+    }
+
+    FieldSubmit {
+        id: claim_data.id,
+        username: claim_data.username.clone(),
+        client_version: CLIENT_VERSION.to_string(),
+        unique_count: None,
+        near_misses: None,
+        nice_list: Some(nice_list),
+        top_near_misses: None,
+        near_miss_details: None,
+        processed_end: None,
+    }
+}
+
+/// Check the residue, then whether the powers share no digits.
+fn is_nice<const N: usize>(
+    num: &FixedNum<N>,
+    context: &BaseContext,
+    residue_divisor: &LimbReciprocal,
+    digits_indicator: &mut [bool],
+) -> bool {
+    // check residue
+    let residue = residue_divisor.rem_limbs(num.limbs()) as u32;
+    if !context.is_residue_kept(residue) {
+        return false;
+    }
+
+    // zero out the indicator
+    digits_indicator.iter_mut().for_each(|x| *x = false);
+    let mut check_digit = |digit: u32| {
+        let seen = digits_indicator[digit as usize];
+        digits_indicator[digit as usize] = true;
+        !seen
+    };
+
+    // square the number (or the lower power) and check those digits, then the cube
+    let (low, high) = num.get_powers(&context.powers);
+    context
+        .extractor
+        .for_each_digit_in_limbs(&mut low.into_limbs(), &mut check_digit)
+        && context
+            .extractor
+            .for_each_digit_in_limbs(&mut high.into_limbs(), &mut check_digit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Get a field of `size` numbers partway through a base.
+    fn get_claim(base: u32, size: u32) -> FieldClaim {
+        let (start, end) = get_base_range(base);
+        let search_start = &start + (&end - &start) / Natural::from(3_u32);
+        FieldClaim {
+            id: 0,
+            username: "benchmark".to_owned(),
+            base,
+            search_end: &search_start + Natural::from(size),
+            search_start,
+            search_range: Natural::from(size),
+        }
+    }
+
+    #[test]
+    fn test_get_limb_count() {
+        for base in MAX_SUPPORTED_BASE_NORMAL + 1..=MAX_SUPPORTED_BASE_HIGH {
+            if base % 5 == 1 {
+                continue;
+            }
+            let claim_data = get_claim(base, 1);
+            let context = BaseContext::new(
+                &claim_data,
+                &PowerSet::SQUARE_CUBE,
+                &NearMissCutoff::default(),
+            );
+            assert!(get_limb_count(&context) <= MAX_LIMBS);
+        }
+    }
+
+    #[test]
+    fn process_detailed_matches_natural() {
+        let options = NearMissOptions {
+            cutoff: NearMissCutoff::Fraction(3, 5),
+            top_k: Some(5),
+            details: true,
+        };
+        for base in [98, 120, 150, 200] {
+            let claim_data = get_claim(base, 500);
+            let context = BaseContext::new(&claim_data, &PowerSet::SQUARE_CUBE, &options.cutoff);
+            assert_eq!(
+                process_detailed(&claim_data, &context, &options),
+                process_natural::process_detailed(&claim_data, &context, &options)
+            );
+        }
+    }
+
+    #[test]
+    fn process_detailed_b120() {
+        // the same field the natural engine checks against
+        let claim_data = FieldClaim {
+            id: 0,
+            username: "benchmark".to_owned(),
+            base: 120,
+            search_start: Natural::from_str("16117196090075248994613996554363597629408239219454")
+                .unwrap(),
+            search_end: Natural::from_str("16117196090075248994613996554363597629408239229454")
+                .unwrap(),
+            search_range: Natural::from(10000_u128),
+        };
+        let options = NearMissOptions::default();
+        let context = BaseContext::new(&claim_data, &PowerSet::SQUARE_CUBE, &options.cutoff);
+        assert_eq!(
+            process_detailed(&claim_data, &context, &options),
+            process_natural::process_detailed(&claim_data, &context, &options)
+        );
+    }
+
+    #[test]
+    fn process_niceonly_matches_natural() {
+        for (base, powers) in [
+            (10, PowerSet::SQUARE_CUBE),
+            (10, PowerSet::new(3, 4).unwrap()),
+            (98, PowerSet::SQUARE_CUBE),
+            (200, PowerSet::SQUARE_CUBE),
+        ] {
+            let claim_data = get_field_benchmark(Some(base), Some(20000), &powers);
+            let context = BaseContext::new(&claim_data, &powers, &NearMissCutoff::default());
+            assert_eq!(
+                process_niceonly(&claim_data, &context),
+                process_natural::process_niceonly(&claim_data, &context)
+            );
+        }
+    }
+}
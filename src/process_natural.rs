@@ -3,53 +3,99 @@
 
 use super::*;
 
+/// Count how many times each digit appears in a number's powers, adding to `digit_counts`.
+fn count_digits(num: &Natural, context: &BaseContext, digit_counts: &mut [u16]) {
+    let (extractor, powers) = (&context.extractor, &context.powers);
+    let mut count_digit = |digit: u32| {
+        digit_counts[digit as usize] += 1;
+        true
+    };
+
+    // square the number (or the lower power) and count those digits
+    let low = powers.get_low_power(num);
+    extractor.for_each_digit(&low, &mut count_digit);
+
+    // cube the number (or the higher power) and count those digits
+    let high = powers.get_high_power(num, &low);
+    extractor.for_each_digit(&high, &mut count_digit);
+}
+
 /// Process a field by aggregating statistics on the niceness of numbers in a range.
-pub fn process_detailed(claim_data: &FieldClaim) -> FieldSubmit {
+pub fn process_detailed(
+    claim_data: &FieldClaim,
+    context: &BaseContext,
+    near_miss_options: &NearMissOptions,
+) -> FieldSubmit {
     let base = claim_data.base;
-    let base_natural = Natural::from(base);
-    let near_misses_cutoff = (base as f32 * NEAR_MISS_CUTOFF_PERCENT) as u32;
 
     // output variables
     let mut unique_digits: u32;
     let mut near_misses: HashMap<String, u32> = HashMap::new();
+    let mut near_miss_details: HashMap<String, NearMissDetail> = HashMap::new();
+    let mut top_cutoff = match near_miss_options.cutoff {
+        NearMissCutoff::TopK(k) => Some(TopNearMisses::new(k)),
+        _ => None,
+    };
+    let mut top_near_misses = near_miss_options.top_k.map(TopNearMisses::new);
     let mut unique_count_vec = vec![0; base as usize];
 
     // iterator variables
-    let mut n: Natural;
     let mut num = claim_data.search_start.clone();
-    let mut digits_indicator = [false; MAX_SUPPORTED_BASE_HIGH as usize];
+    let mut digit_counts = [0; MAX_SUPPORTED_BASE_HIGH as usize];
 
     while num < claim_data.search_end {
-        // zero out the indicator
-        digits_indicator.iter_mut().for_each(|x| *x = false);
-
-        // square the number and save those digits
-        n = (&num).pow(2);
-        while n > 0 {
-            let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-            digits_indicator[remainder] = true;
-        }
-
-        // cube the number and save those digits
-        n = (&num).pow(3);
-        while n > 0 {
-            let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-            digits_indicator[remainder] = true;
-        }
+        // zero out the counts, then count the digits of the square and cube
+        digit_counts.iter_mut().for_each(|x| *x = 0);
+        count_digits(&num, context, &mut digit_counts);
 
         // count the digits, update the unique count
-        unique_digits = digits_indicator.iter().filter(|&&x| x).count() as u32;
+        unique_digits = digit_counts.iter().filter(|&&x| x > 0).count() as u32;
         unique_count_vec[unique_digits as usize - 1] += 1;
 
         // save if the number is pretty nice
-        if unique_digits > near_misses_cutoff {
+        if let Some(top) = &mut top_cutoff {
+            if top.accepts(unique_digits) {
+                top.push(num.clone(), unique_digits);
+            }
+        } else if unique_digits > context.near_misses_cutoff {
             near_misses.insert(num.to_string(), unique_digits);
+            if near_miss_options.details {
+                near_miss_details.insert(
+                    num.to_string(),
+                    NearMissDetail::from_digit_counts(&digit_counts[..base as usize]),
+                );
+            }
+        }
+        if let Some(top) = &mut top_near_misses {
+            if top.accepts(unique_digits) {
+                top.push(num.clone(), unique_digits);
+            }
         }
 
         // increment num
         num += Natural::ONE;
     }
 
+    // the top numbers are only known at the end, so recount their digits for details
+    let top_cutoff = top_cutoff.map(TopNearMisses::into_vec);
+    let top_near_misses = top_near_misses.map(TopNearMisses::into_sorted_vec);
+    if near_miss_options.details {
+        for (num, _) in top_cutoff.iter().chain(top_near_misses.iter()).flatten() {
+            let mut digit_counts = vec![0; base as usize];
+            count_digits(num, context, &mut digit_counts);
+            near_miss_details.insert(
+                num.to_string(),
+                NearMissDetail::from_digit_counts(&digit_counts),
+            );
+        }
+    }
+    if let Some(top) = top_cutoff {
+        near_misses = top
+            .into_iter()
+            .map(|(num, value)| (num.to_string(), value))
+            .collect();
+    }
+
     // sum unique counts from vec
     let unique_count = unique_count_vec
         .iter()
@@ -64,56 +110,87 @@ pub fn process_detailed(claim_data: &FieldClaim) -> FieldSubmit {
         unique_count: Some(unique_count),
         near_misses: Some(near_misses),
         nice_list: None,
+        top_near_misses: top_near_misses.map(|top| {
+            top.into_iter()
+                .map(|(num, value)| (num.to_string(), value))
+                .collect()
+        }),
+        near_miss_details: near_miss_options.details.then_some(near_miss_details),
+        processed_end: None,
     };
 }
 
 /// Process a field by looking for completely nice numbers.
 /// Implements several optimizations over the detailed search.
-pub fn process_niceonly(claim_data: &FieldClaim) -> FieldSubmit {
+pub fn process_niceonly(claim_data: &FieldClaim, context: &BaseContext) -> FieldSubmit {
     let base = claim_data.base;
-    let base_natural = Natural::from(base);
-    let base_natural_sub_one = Natural::from(base) - Natural::ONE;
+    let (extractor, powers) = (&context.extractor, &context.powers);
+    let base_natural_sub_one = &context.base_natural - Natural::ONE;
+    debug!(
+        base,
+        residues_kept = context.residues_kept(),
+        residues_total = base - 1,
+        "residue filter"
+    );
 
-    let residue_filter = get_residue_filter(&base);
+    // skip anything with repeats in the leading digits before checking number by number
+    let ranges = get_candidate_ranges(
+        base,
+        powers,
+        &claim_data.search_start,
+        &claim_data.search_end,
+        LEADING_DIGITS_LEAF_SIZE,
+    );
+    debug!(
+        base,
+        ranges = ranges.len(),
+        kept = %ranges.iter().fold(Natural::ZERO, |acc, (start, end)| acc + end - start),
+        total = %claim_data.search_range,
+        "leading digit filter"
+    );
 
     // output & iterator variables
     let mut nice_list = Vec::new();
-    let mut num = &claim_data.search_start - Natural::ONE;
+    let mut ranges = ranges.into_iter();
+    let mut range_end = Natural::ZERO;
+    let mut num = Natural::ZERO;
     let mut digits_indicator = [false; MAX_SUPPORTED_BASE_HIGH as usize];
 
-    'search_range: while num < claim_data.search_end {
-        // increment num
+    loop {
+        // increment num, jumping to the next range at the end of this one
         num += Natural::ONE;
+        if num >= range_end {
+            match ranges.next() {
+                Some((start, end)) => (num, range_end) = (start, end),
+                None => break,
+            }
+        }
 
         // check residue
         let remainder = (&num).mod_op(&base_natural_sub_one);
         let residue = u32::try_from(&remainder).unwrap();
-        if !residue_filter.contains(&residue) {
+        if !context.is_residue_kept(residue) {
             continue;
         }
 
         // zero out the indicator
         digits_indicator.iter_mut().for_each(|x| *x = false);
 
-        // square the number and check those digits
-        let squared = (&num).pow(2);
-        let mut n = squared.clone();
-        while n > 0 {
-            let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-            if digits_indicator[remainder] {
-                continue 'search_range;
-            }
-            digits_indicator[remainder] = true;
+        // square the number (or the lower power) and check those digits
+        let mut check_digit = |digit: u32| {
+            let seen = digits_indicator[digit as usize];
+            digits_indicator[digit as usize] = true;
+            !seen
+        };
+        let low = powers.get_low_power(&num);
+        if !extractor.for_each_digit(&low, &mut check_digit) {
+            continue;
         }
 
-        // cube the number and check those digits
-        let mut n = squared * &num;
-        while n > 0 {
-            let remainder = usize::try_from(&(n.div_assign_rem(&base_natural))).unwrap();
-            if digits_indicator[remainder] {
-                continue 'search_range;
-            }
-            digits_indicator[remainder] = true;
+        // cube the number (or the higher power) and check those digits
+        let high = powers.get_high_power(&num, &low);
+        if !extractor.for_each_digit(&high, &mut check_digit) {
+            continue;
         }
 
         // save the number!
@@ -127,6 +204,9 @@ pub fn process_niceonly(claim_data: &FieldClaim) -> FieldSubmit {
         unique_count: None,
         near_misses: None,
         nice_list: Some(nice_list),
+        top_near_misses: None,
+        near_miss_details: None,
+        processed_end: None,
     };
 }
 
@@ -135,6 +215,15 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
+    /// Precompute the square-cube context for a test field.
+    fn get_context(claim_data: &FieldClaim) -> BaseContext {
+        BaseContext::new(
+            claim_data,
+            &PowerSet::SQUARE_CUBE,
+            &NearMissCutoff::default(),
+        )
+    }
+
     #[test]
     fn process_detailed_b10() {
         let claim_data = FieldClaim {
@@ -163,8 +252,18 @@ mod tests {
             ])),
             near_misses: Some(HashMap::from([("69".to_string(), 10)])),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -225,8 +324,18 @@ mod tests {
             ])),
             near_misses: Some(HashMap::new()),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -327,8 +436,18 @@ mod tests {
             ])),
             near_misses: Some(HashMap::new()),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -471,8 +590,18 @@ mod tests {
             ])),
             near_misses: Some(HashMap::new()),
             nice_list: None,
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_detailed(&claim_data), submit_data);
+        assert_eq!(
+            process_detailed(
+                &claim_data,
+                &get_context(&claim_data),
+                &NearMissOptions::default()
+            ),
+            submit_data
+        );
     }
 
     #[test]
@@ -492,8 +621,14 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::from(["69".to_string()])),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data)),
+            submit_data
+        );
     }
 
     #[test]
@@ -513,8 +648,14 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::new()),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data)),
+            submit_data
+        );
     }
 
     #[test]
@@ -534,8 +675,14 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::new()),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data)),
+            submit_data
+        );
     }
 
     #[test]
@@ -557,7 +704,13 @@ mod tests {
             unique_count: None,
             near_misses: None,
             nice_list: Some(Vec::new()),
+            top_near_misses: None,
+            near_miss_details: None,
+            processed_end: None,
         };
-        assert_eq!(process_niceonly(&claim_data), submit_data);
+        assert_eq!(
+            process_niceonly(&claim_data, &get_context(&claim_data)),
+            submit_data
+        );
     }
 }
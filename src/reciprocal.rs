@@ -0,0 +1,189 @@
+//! A module for dividing by the same number over and over without a hardware divide.
+//! Each divisor gets a precomputed reciprocal, so every division is a multiply and a few shifts.
+//! For more information: https://gmplib.org/~tege/divcnst-pldi94.pdf (used by libdivide)
+//! and https://gmplib.org/~tege/division-paper.pdf for dividing numbers longer than a word.
+
+/// A divisor for u64 numbers, with its reciprocal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reciprocal {
+    divisor: u64,
+    multiplier: u64,
+    shift: u32,
+}
+
+impl Reciprocal {
+    /// Precompute the reciprocal of a divisor, which must be between 2 and 2^63.
+    pub fn new(divisor: u64) -> Self {
+        assert!(
+            (2..=1 << 63).contains(&divisor),
+            "Cannot take the reciprocal of {}",
+            divisor
+        );
+        // shift = ceil(log2(divisor)), multiplier = floor(2^(64 + shift) / divisor) - 2^64 + 1
+        let shift = 64 - (divisor - 1).leading_zeros();
+        let multiplier = ((1_u128 << (64 + shift)) / divisor as u128 - (1_u128 << 64) + 1) as u64;
+        Reciprocal {
+            divisor,
+            multiplier,
+            shift,
+        }
+    }
+
+    pub fn divisor(&self) -> u64 {
+        self.divisor
+    }
+
+    /// Get the quotient and remainder of `num / divisor`.
+    #[inline]
+    pub fn div_rem(&self, num: u64) -> (u64, u64) {
+        let high = ((num as u128 * self.multiplier as u128) >> 64) as u64;
+        let quotient = (high + ((num - high) >> 1)) >> (self.shift - 1);
+        (quotient, num - quotient * self.divisor)
+    }
+}
+
+/// A divisor for numbers made up of many u64 limbs, with its reciprocal.
+/// The divisor is shifted up until its top bit is set, and the numbers with it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LimbReciprocal {
+    normalized: u64,
+    reciprocal: u64,
+    shift: u32,
+}
+
+impl LimbReciprocal {
+    /// Precompute the reciprocal of a divisor, which must not be zero.
+    /// This is a const fn so kernels for a fixed base can bake it in.
+    pub const fn new(divisor: u64) -> Self {
+        assert!(divisor > 0, "Cannot take the reciprocal of 0");
+        let shift = divisor.leading_zeros();
+        let normalized = divisor << shift;
+        // floor((2^128 - 1) / normalized) - 2^64
+        let reciprocal = (u128::MAX / normalized as u128 - (1_u128 << 64)) as u64;
+        LimbReciprocal {
+            normalized,
+            reciprocal,
+            shift,
+        }
+    }
+
+    /// Divide the two-limb number `high:low` by the normalized divisor. Needs `high < normalized`.
+    #[inline]
+    fn div_rem_2by1(&self, high: u64, low: u64) -> (u64, u64) {
+        let product = self.reciprocal as u128 * high as u128;
+        let estimate = product.wrapping_add(((high as u128 + 1) << 64) | low as u128);
+        let mut quotient = (estimate >> 64) as u64;
+        let mut remainder = low.wrapping_sub(quotient.wrapping_mul(self.normalized));
+        if remainder > estimate as u64 {
+            quotient = quotient.wrapping_sub(1);
+            remainder = remainder.wrapping_add(self.normalized);
+        }
+        if remainder >= self.normalized {
+            quotient += 1;
+            remainder -= self.normalized;
+        }
+        (quotient, remainder)
+    }
+
+    /// Shift a limb up as much as the divisor was, pulling in the top bits of the limb below it.
+    /// Shifting through u128 means a shift of zero doesn't need a special case.
+    #[inline]
+    fn normalize(&self, high: u64, low: u64) -> u64 {
+        (((high as u128) << 64 | low as u128) >> (64 - self.shift)) as u64
+    }
+
+    /// Divide a number stored as u64 limbs (least significant first) in place, returning the remainder.
+    pub fn div_rem_limbs(&self, limbs: &mut [u64]) -> u64 {
+        let mut remainder = self.normalize(0, *limbs.last().unwrap_or(&0));
+        for i in (0..limbs.len()).rev() {
+            let next = if i > 0 { limbs[i - 1] } else { 0 };
+            let (quotient, rest) = self.div_rem_2by1(remainder, self.normalize(limbs[i], next));
+            limbs[i] = quotient;
+            remainder = rest;
+        }
+        remainder >> self.shift
+    }
+
+    /// Get the remainder of a number stored as u64 limbs (least significant first), leaving it be.
+    pub fn rem_limbs(&self, limbs: &[u64]) -> u64 {
+        let mut remainder = self.normalize(0, *limbs.last().unwrap_or(&0));
+        for i in (0..limbs.len()).rev() {
+            let next = if i > 0 { limbs[i - 1] } else { 0 };
+            remainder = self
+                .div_rem_2by1(remainder, self.normalize(limbs[i], next))
+                .1;
+        }
+        remainder >> self.shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use malachite::natural::Natural;
+    use malachite::num::arithmetic::traits::DivRem;
+    use rand::{Rng, SeedableRng};
+
+    /// Numerators that tend to break division tricks, plus some random ones.
+    fn get_numerators() -> Vec<u64> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut numerators = vec![0, 1, 2, 3, u64::MAX, u64::MAX - 1, 1 << 63, (1 << 63) - 1];
+        numerators.extend((0..1000).map(|_| rng.gen::<u64>()));
+        numerators.extend((0..1000).map(|_| rng.gen::<u64>() >> rng.gen_range(0..64)));
+        numerators
+    }
+
+    /// Divisors like the bases and chunk divisors we use, plus powers of two and random ones.
+    fn get_divisors() -> Vec<u64> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut divisors: Vec<u64> = (2..=300).collect();
+        for base in 2..=200_u64 {
+            let mut power = base;
+            while let Some(next) = power.checked_mul(base) {
+                power = next;
+            }
+            divisors.push(power);
+        }
+        divisors.extend((1..64).map(|shift| 1 << shift));
+        divisors.extend([u64::MAX, u64::MAX - 1, (1 << 63) + 1]);
+        divisors.extend((0..100).map(|_| rng.gen::<u64>().max(2)));
+        divisors
+    }
+
+    #[test]
+    fn test_reciprocal() {
+        let numerators = get_numerators();
+        for divisor in get_divisors().into_iter().filter(|&d| d <= 1 << 63) {
+            let reciprocal = Reciprocal::new(divisor);
+            for &num in &numerators {
+                assert_eq!(
+                    reciprocal.div_rem(num),
+                    (num / divisor, num % divisor),
+                    "{} / {}",
+                    num,
+                    divisor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_limb_reciprocal() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let mut divisors = get_divisors();
+        divisors.push(1);
+        for divisor in divisors {
+            let reciprocal = LimbReciprocal::new(divisor);
+            for length in [0, 1, 2, 5, 16] {
+                let limbs: Vec<u64> = (0..length).map(|_| rng.gen()).collect();
+                let num = Natural::from_limbs_asc(&limbs);
+                let mut quotient = limbs.clone();
+                let remainder = reciprocal.div_rem_limbs(&mut quotient);
+                assert_eq!(reciprocal.rem_limbs(&limbs), remainder);
+                let (expected_quotient, expected_remainder) = num.div_rem(Natural::from(divisor));
+                assert_eq!(Natural::from_limbs_asc(&quotient), expected_quotient);
+                assert_eq!(Natural::from(remainder), expected_remainder);
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 //! A module for deaing with residue filters
 //! For more information: https://beautifulthorns.wixsite.com/home/post/progress-update-on-the-search-for-nice-numbers
 
+use super::*;
+
 /// Get a list of residue filters for a base.
 pub fn get_residue_filter(base: &u32) -> Vec<u32> {
     let target_residue = base * (base - 1) / 2 % (base - 1);
@@ -9,6 +11,28 @@ pub fn get_residue_filter(base: &u32) -> Vec<u32> {
         .collect()
 }
 
+/// Get a list of residue filters for a base with any pair of powers.
+/// A nice number's digits sum to base * (base - 1) / 2, and a digit sum is the number mod (base - 1).
+pub fn get_power_set_residue_filter(base: &u32, powers: &PowerSet) -> Vec<u32> {
+    match powers.is_square_cube() {
+        true => get_residue_filter(base),
+        false => get_any_residue_filter(base, powers),
+    }
+}
+
+/// Get a list of residue filters with modular powers, so large exponents don't overflow.
+fn get_any_residue_filter(base: &u32, powers: &PowerSet) -> Vec<u32> {
+    let modulus = (base - 1) as u64;
+    let target_residue = (base * (base - 1) / 2) as u64 % modulus;
+    let pow_mod = |num: u64, exp: u32| (0..exp).fold(1 % modulus, |acc, _| acc * num % modulus);
+    (0..(base - 1))
+        .filter(|&num| {
+            let num = num as u64;
+            (pow_mod(num, powers.low) + pow_mod(num, powers.high)) % modulus == target_residue
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,4 +88,23 @@ mod tests {
         assert_eq!(get_residue_filter(&119), Vec::<u32>::new());
         assert_eq!(get_residue_filter(&120), Vec::from([0, 34, 84, 118]));
     }
+
+    #[test]
+    fn test_get_power_set_residue_filter() {
+        for base in MIN_SUPPORTED_BASE..=MAX_SUPPORTED_BASE_HIGH {
+            assert_eq!(
+                get_any_residue_filter(&base, &PowerSet::SQUARE_CUBE),
+                get_residue_filter(&base)
+            );
+        }
+        // n^2 + n^4 must be 0 mod 9 in base 10
+        assert_eq!(
+            get_power_set_residue_filter(&10, &PowerSet::new(2, 4).unwrap()),
+            Vec::from([0, 3, 6])
+        );
+        assert_eq!(
+            get_power_set_residue_filter(&12, &PowerSet::new(3, 4).unwrap()),
+            Vec::from([0, 10])
+        );
+    }
 }
@@ -0,0 +1,247 @@
+//! A module for keeping a local record of every submitted field.
+//! Records are appended to a JSON Lines file, so an interrupted write loses at most one line,
+//! and a cut-off last line is skipped when reading rather than making the whole file unreadable.
+
+use super::*;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A submitted field as saved in the results file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldRecord {
+    pub result: FieldResult,
+    pub engine: String,
+    pub client_version: String,
+    /// Seconds since the Unix epoch.
+    pub submitted_at: u64,
+}
+
+impl FieldRecord {
+    /// Build a record for a field that was just submitted.
+    pub fn new(result: FieldResult, engine: &str) -> Self {
+        FieldRecord {
+            result,
+            engine: engine.to_string(),
+            client_version: CLIENT_VERSION.to_string(),
+            submitted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+}
+
+/// Discoveries aggregated over every recorded field in one base.
+#[derive(Debug, PartialEq)]
+pub struct BaseTotals {
+    pub fields: usize,
+    pub numbers_checked: Natural,
+    pub near_misses: usize,
+    pub nice_numbers: usize,
+}
+
+/// Add a record to the end of the results file, creating it if needed.
+/// A last line cut off by an interrupted write is dropped first, so it can't end up mid-file.
+pub fn append_record(path: &Path, record: &FieldRecord) -> Result<(), String> {
+    let line =
+        serde_json::to_string(record).map_err(|e| format!("Error serializing results: {}", e))?;
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| {
+            let len = file.metadata()?.len();
+            let mut last = [b'\n'];
+            if len > 0 {
+                file.seek(SeekFrom::Start(len - 1))?;
+                file.read_exact(&mut last)?;
+            }
+            if last[0] != b'\n' {
+                let mut contents = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut contents)?;
+                let end = contents
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |i| i + 1);
+                file.set_len(end as u64)?;
+            }
+            writeln!(file, "{}", line)
+        })
+        .map_err(|e| format!("Error writing results {}: {}", path.display(), e))
+}
+
+/// Load every record from the results file. A missing file has no records.
+/// A last line that can't be read was most likely cut off mid-write, so it is skipped,
+/// and a warning saying so is returned along with the records. Anywhere else it's an error.
+pub fn read_records(path: &Path) -> Result<(Vec<FieldRecord>, Option<String>), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), None)),
+        Err(e) => return Err(format!("Error reading results {}: {}", path.display(), e)),
+    };
+    let lines: Vec<_> = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let mut records = Vec::with_capacity(lines.len());
+    for (n, &(i, line)) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(e) if n + 1 == lines.len() => {
+                let warning = format!(
+                    "Skipping unreadable last line {} of results {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                );
+                warn!("{}", warning);
+                return Ok((records, Some(warning)));
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Error reading results {} line {}: {}",
+                    path.display(),
+                    i + 1,
+                    e
+                ))
+            }
+        }
+    }
+    Ok((records, None))
+}
+
+/// Total up the records for each base.
+pub fn get_base_totals(records: &[FieldRecord]) -> BTreeMap<u32, BaseTotals> {
+    let mut totals = BTreeMap::new();
+    for record in records {
+        let result = &record.result;
+        let base_totals = totals
+            .entry(result.claim_data.base)
+            .or_insert_with(|| BaseTotals {
+                fields: 0,
+                numbers_checked: Natural::ZERO,
+                near_misses: 0,
+                nice_numbers: 0,
+            });
+        base_totals.fields += 1;
+        base_totals.numbers_checked += &result.claim_data.search_range;
+        base_totals.near_misses += result
+            .submit_data
+            .near_misses
+            .as_ref()
+            .map_or(0, |n| n.len());
+        base_totals.nice_numbers += result.nice_numbers().len();
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_record(mode: Mode, base: u32) -> FieldRecord {
+        let claim_data = get_field_benchmark(Some(base), Some(1000), &PowerSet::SQUARE_CUBE);
+        let submit_data = process_field(
+            &mode,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &NearMissOptions::default(),
+            &PowerSet::SQUARE_CUBE,
+            &claim_data,
+        );
+        FieldRecord::new(
+            FieldResult {
+                mode,
+                claim_data,
+                submit_data,
+                elapsed: Duration::from_millis(1500),
+            },
+            "integer",
+        )
+    }
+
+    #[test]
+    fn test_results_roundtrip() {
+        let path = env::temp_dir().join(format!("nice-results-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(read_records(&path).unwrap().0.is_empty());
+
+        let records = [
+            get_record(Mode::Detailed, 10),
+            get_record(Mode::Niceonly, 10),
+            get_record(Mode::Detailed, 40),
+        ];
+        for record in &records {
+            append_record(&path, record).unwrap();
+        }
+
+        let (loaded, warning) = read_records(&path).unwrap();
+        assert!(warning.is_none());
+        assert_eq!(loaded.len(), records.len());
+        for (loaded, record) in loaded.iter().zip(records.iter()) {
+            assert_eq!(loaded.result.mode, record.result.mode);
+            assert_eq!(loaded.result.claim_data.id, record.result.claim_data.id);
+            assert_eq!(
+                loaded.result.claim_data.search_range,
+                record.result.claim_data.search_range
+            );
+            assert_eq!(loaded.result.submit_data, record.result.submit_data);
+            assert_eq!(loaded.result.elapsed, record.result.elapsed);
+            assert_eq!(loaded.engine, "integer");
+        }
+
+        // a write cut off partway through only loses that record
+        let line = serde_json::to_string(&records[0]).unwrap();
+        let append_cut_off = |newline: &str| {
+            OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| write!(file, "{}{}", &line[..line.len() / 2], newline))
+                .unwrap()
+        };
+        append_cut_off("");
+        let (loaded, warning) = read_records(&path).unwrap();
+        assert!(warning.is_some());
+        assert_eq!(loaded.len(), records.len());
+
+        // and the next write replaces it
+        append_record(&path, &records[0]).unwrap();
+        let (loaded, warning) = read_records(&path).unwrap();
+        assert!(warning.is_none());
+        assert_eq!(loaded.len(), records.len() + 1);
+
+        // but a bad line anywhere else is still an error
+        append_cut_off("\n");
+        append_record(&path, &records[0]).unwrap();
+        assert!(read_records(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_get_base_totals() {
+        let records = [
+            get_record(Mode::Detailed, 10),
+            get_record(Mode::Niceonly, 10),
+            get_record(Mode::Detailed, 40),
+        ];
+        let totals = get_base_totals(&records);
+        assert_eq!(totals.len(), 2);
+        // 69 is found by both modes, and counts as a near miss in detailed
+        assert_eq!(
+            totals[&10],
+            BaseTotals {
+                fields: 2,
+                numbers_checked: Natural::from(106_u32),
+                near_misses: 1,
+                nice_numbers: 2,
+            }
+        );
+        assert_eq!(totals[&40].fields, 1);
+        assert_eq!(totals[&40].numbers_checked, Natural::from(1000_u32));
+    }
+}
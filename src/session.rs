@@ -0,0 +1,675 @@
+//! A module for processing many fields in a row and aggregating the results.
+
+use super::*;
+
+/// Conditions that end a session early. Unset limits are unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct SessionLimits {
+    /// Stop after this many fields have been completed.
+    pub max_fields: Option<u32>,
+    /// Stop claiming new fields after this much time has passed.
+    pub max_duration: Option<Duration>,
+    /// Give up after this many failed runs in a row.
+    pub max_consecutive_failures: Option<u32>,
+}
+
+/// Statistics aggregated over every field in a session.
+#[derive(Debug)]
+pub struct SessionSummary {
+    pub fields_completed: u32,
+    pub fields_failed: u32,
+    /// Prefetched claims that waited too long to process and were released instead.
+    pub fields_expired: u32,
+    /// Interrupted fields whose processed part was submitted.
+    pub fields_partial: u32,
+    pub numbers_checked: Natural,
+    pub processing_time: Duration,
+    pub elapsed: Duration,
+    pub near_misses: usize,
+    pub nice_numbers: Vec<String>,
+    pub last_outcome: Option<RunOutcome>,
+}
+
+impl SessionSummary {
+    fn new() -> Self {
+        SessionSummary {
+            fields_completed: 0,
+            fields_failed: 0,
+            fields_expired: 0,
+            fields_partial: 0,
+            numbers_checked: Natural::ZERO,
+            processing_time: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            near_misses: 0,
+            nice_numbers: Vec::new(),
+            last_outcome: None,
+        }
+    }
+
+    /// Add a completed field, or the processed part of a partially submitted one, to the totals.
+    fn record(&mut self, result: &FieldResult) {
+        match result.submit_data.processed_end {
+            None => self.fields_completed += 1,
+            Some(_) => self.fields_partial += 1,
+        }
+        self.numbers_checked += &result.claim_data.search_range;
+        self.processing_time += result.elapsed;
+        if let Some(near_misses) = &result.submit_data.near_misses {
+            self.near_misses += near_misses.len();
+        }
+        self.nice_numbers.extend(result.nice_numbers());
+    }
+
+    /// Average numbers checked per second of processing.
+    pub fn hash_rate(&self) -> f64 {
+        if self.processing_time.is_zero() {
+            return 0.0;
+        }
+        f64::try_from(&self.numbers_checked).unwrap() / self.processing_time.as_secs_f64()
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Fields completed: {} ({} partial, {} failed, {} expired)",
+            self.fields_completed, self.fields_partial, self.fields_failed, self.fields_expired
+        )?;
+        writeln!(
+            f,
+            "Numbers checked:  {:.3e}",
+            f64::try_from(&self.numbers_checked).unwrap()
+        )?;
+        writeln!(f, "Elapsed time:     {:.3?}", self.elapsed)?;
+        writeln!(f, "Hash rate:        {:.3e}", self.hash_rate())?;
+        writeln!(f, "Near misses:      {}", self.near_misses)?;
+        write!(f, "Nice numbers:     {:?}", self.nice_numbers)
+    }
+}
+
+/// Options for claiming and submitting fields in the background.
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    /// How many claims to keep ready while the current field is processed.
+    pub depth: usize,
+    /// Claims that would be finished longer than this after they were claimed
+    /// have likely expired on the server by then, and are skipped.
+    pub claim_expiry: Duration,
+}
+
+/// Check whether any of the session limits have been hit.
+fn limits_reached(limits: &SessionLimits, fields_completed: u32, elapsed: Duration) -> bool {
+    if let Some(max_fields) = limits.max_fields {
+        if fields_completed >= max_fields {
+            return true;
+        }
+    }
+    if let Some(max_duration) = limits.max_duration {
+        if elapsed >= max_duration {
+            return true;
+        }
+    }
+    false
+}
+
+/// Sleep for the given duration, waking early if any of the signals is triggered.
+fn sleep_unless_triggered(duration: Duration, signals: &[&ShutdownSignal]) {
+    let wake = Instant::now() + duration;
+    while !signals.iter().any(|signal| signal.is_triggered()) && Instant::now() < wake {
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+/// Estimate how long a field will take to process from `start` on at the given hash rate.
+/// Before anything has been processed there's no rate to go on, so the estimate is zero.
+fn estimate_processing_time(claim_data: &FieldClaim, start: &Natural, hash_rate: f64) -> Duration {
+    if hash_rate <= 0.0 {
+        return Duration::ZERO;
+    }
+    let remaining = f64::try_from(&(&claim_data.search_end - start)).unwrap_or(f64::MAX);
+    Duration::try_from_secs_f64(remaining / hash_rate).unwrap_or(Duration::MAX)
+}
+
+/// Get a readable message out of a caught panic.
+fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown error".to_string()
+    }
+}
+
+/// Run fields back to back until a limit is hit or a shutdown is requested.
+/// A failed run (network error, bad response, etc.) is retried after an increasing delay.
+pub fn run_session(
+    config: &Config,
+    client: &ApiClient,
+    limits: &SessionLimits,
+    shutdown: &ShutdownSignal,
+) -> SessionSummary {
+    let started = Instant::now();
+    let mut summary = SessionSummary::new();
+    let mut consecutive_failures = 0;
+    let mut retry_delay = SESSION_RETRY_DELAY_MIN;
+
+    while !shutdown.is_triggered()
+        && !limits_reached(limits, summary.fields_completed, started.elapsed())
+    {
+        let outcome =
+            match panic::catch_unwind(panic::AssertUnwindSafe(|| run(config, client, shutdown))) {
+                Ok(outcome) => Some(outcome),
+                Err(payload) => {
+                    error!(reason = panic_message(&payload), "run failed");
+                    None
+                }
+            };
+        if let Some(RunOutcome::Completed(result) | RunOutcome::PartiallySubmitted(result)) =
+            &outcome
+        {
+            summary.record(result);
+        }
+        let failed = matches!(outcome, None | Some(RunOutcome::Rejected { .. }));
+        if outcome.is_some() {
+            summary.last_outcome = outcome;
+        }
+        if !failed {
+            consecutive_failures = 0;
+            retry_delay = SESSION_RETRY_DELAY_MIN;
+            continue;
+        }
+
+        // back off before trying again
+        summary.fields_failed += 1;
+        consecutive_failures += 1;
+        if let Some(max_failures) = limits.max_consecutive_failures {
+            if consecutive_failures >= max_failures {
+                break;
+            }
+        }
+        warn!(?retry_delay, consecutive_failures, "retrying after failure");
+        sleep_unless_triggered(retry_delay, &[shutdown]);
+        retry_delay = (retry_delay * 2).min(SESSION_RETRY_DELAY_MAX);
+    }
+
+    summary.elapsed = started.elapsed();
+    summary
+}
+
+/// A prefetched field, with the results so far if it was resumed, and when it was claimed.
+type Claimed = (Mode, FieldClaim, Option<PartialProgress>, Instant);
+
+/// Like `sleep_unless_triggered`, but waits on the runtime instead of holding up its thread.
+async fn wait_unless_triggered(duration: Duration, signals: &[&ShutdownSignal]) {
+    let wake = Instant::now() + duration;
+    while !signals.iter().any(|signal| signal.is_triggered()) && Instant::now() < wake {
+        tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+}
+
+/// Get a readable message out of a task that failed.
+fn join_error_message(e: tokio::task::JoinError) -> String {
+    match e.try_into_panic() {
+        Ok(payload) => panic_message(&payload),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Keep claiming fields until told to stop, retrying failed claims after an increasing delay.
+/// Each claim waits for room in the channel first, so no more than it holds are ever claimed.
+async fn claim_ahead(
+    config: Arc<Config>,
+    client: ApiClient,
+    claims: tokio::sync::mpsc::Sender<Claimed>,
+    shutdown: ShutdownSignal,
+    stop_claiming: ShutdownSignal,
+    claim_failures: Arc<AtomicU32>,
+) {
+    let mut retry_delay = SESSION_RETRY_DELAY_MIN;
+    while !stop_claiming.is_triggered() && !shutdown.is_triggered() {
+        let permit = match claims.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+        if stop_claiming.is_triggered() || shutdown.is_triggered() {
+            break;
+        }
+
+        // claim in a task of its own, so a failed claim doesn't take the claimer down with it
+        let claim = tokio::spawn({
+            let (config, client) = (config.clone(), client.clone());
+            async move { claim_field(&config, &client).await }
+        });
+        match claim.await {
+            Ok((mode, claim_data, progress)) => {
+                retry_delay = SESSION_RETRY_DELAY_MIN;
+                permit.send((mode, claim_data, progress, Instant::now()));
+            }
+            Err(e) => {
+                drop(permit);
+                claim_failures.fetch_add(1, Ordering::SeqCst);
+                warn!(
+                    reason = join_error_message(e),
+                    ?retry_delay,
+                    "claim failed, retrying"
+                );
+                wait_unless_triggered(retry_delay, &[&shutdown, &stop_claiming]).await;
+                retry_delay = (retry_delay * 2).min(SESSION_RETRY_DELAY_MAX);
+            }
+        }
+    }
+}
+
+/// Submit finished fields in the order they come in, passing back each one that went through,
+/// or the id of each one that didn't.
+async fn submit_finished(
+    config: Arc<Config>,
+    client: ApiClient,
+    mut results: tokio::sync::mpsc::UnboundedReceiver<Box<FieldResult>>,
+    submitted: mpsc::Sender<Result<Box<FieldResult>, u32>>,
+) {
+    while let Some(result) = results.recv().await {
+        let id = result.claim_data.id;
+        let submit = tokio::spawn({
+            let (config, client) = (config.clone(), client.clone());
+            async move {
+                submit_field(&config, &client, &result).await;
+                result
+            }
+        });
+        match submit.await {
+            Ok(result) => submitted.send(Ok(result)).unwrap(),
+            Err(e) => {
+                error!(id, reason = join_error_message(e), "submit failed");
+                submitted.send(Err(id)).unwrap()
+            }
+        }
+    }
+}
+
+/// Run fields back to back like `run_session`, but keep up to `pipeline.depth` fields
+/// claimed ahead of time and submit finished fields in the background.
+/// Claims and submits run as tasks on the client's runtime, while fields are processed here.
+/// Any prefetched claims left over at the end are released back to the server.
+pub fn run_pipelined_session(
+    config: &Config,
+    client: &ApiClient,
+    limits: &SessionLimits,
+    pipeline: &PipelineOptions,
+    shutdown: &ShutdownSignal,
+) -> SessionSummary {
+    let started = Instant::now();
+    let mut summary = SessionSummary::new();
+    let stop_claiming = ShutdownSignal::new();
+    let claim_failures = Arc::new(AtomicU32::new(0));
+
+    // claim fields ahead of time and submit finished ones in the background
+    let shared_config = Arc::new(config.clone());
+    let (claim_sender, mut claim_receiver) = tokio::sync::mpsc::channel(pipeline.depth.max(1));
+    let (result_sender, result_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (submitted_sender, submitted_receiver) = mpsc::channel();
+    let claimer = client.spawn(claim_ahead(
+        shared_config.clone(),
+        client.clone(),
+        claim_sender,
+        shutdown.clone(),
+        stop_claiming.clone(),
+        claim_failures.clone(),
+    ));
+    let submitter = client.spawn(submit_finished(
+        shared_config,
+        client.clone(),
+        result_receiver,
+        submitted_sender,
+    ));
+
+    // process fields as they become available, keeping track of how fast that goes
+    let mut fields_processed = 0;
+    let mut processed = SessionSummary::new();
+    while !shutdown.is_triggered() && !limits_reached(limits, fields_processed, started.elapsed()) {
+        // wake up now and then to check the limits while the claimer is retrying
+        let claim = client.block_on(async {
+            tokio::time::timeout(SHUTDOWN_POLL_INTERVAL, claim_receiver.recv()).await
+        });
+        let (mode, claim_data, progress, claimed_at): Claimed = match claim {
+            Ok(Some(claim)) => claim,
+            Ok(None) => break,
+            Err(_) => continue,
+        };
+
+        // a resumed field only has the rest left to do
+        let start = progress
+            .as_ref()
+            .map_or(&claim_data.search_start, |(_, next)| next);
+        let age = claimed_at.elapsed();
+        let estimate = estimate_processing_time(&claim_data, start, processed.hash_rate());
+        if age.saturating_add(estimate) > pipeline.claim_expiry {
+            warn!(
+                id = claim_data.id,
+                ?age,
+                ?estimate,
+                "releasing field, the claim will likely expire before it's finished"
+            );
+            let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                client.block_on(release_field(config, client, &mode, &claim_data))
+            }));
+            summary.fields_expired += 1;
+            continue;
+        }
+        let claimed = (mode, claim_data.clone());
+        match panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            process_claimed_field(config, client, shutdown, mode, claim_data, progress)
+        })) {
+            Ok(RunOutcome::Completed(result)) => {
+                fields_processed += 1;
+                processed.record(&result);
+                result_sender.send(result).unwrap();
+            }
+            Ok(RunOutcome::PartiallySubmitted(result)) => {
+                processed.record(&result);
+                summary.record(&result);
+                summary.last_outcome = Some(RunOutcome::PartiallySubmitted(result));
+            }
+            Ok(outcome @ RunOutcome::Rejected { .. }) => {
+                summary.fields_failed += 1;
+                summary.last_outcome = Some(outcome);
+            }
+            Ok(outcome) => summary.last_outcome = Some(outcome),
+            Err(payload) => {
+                // hand the field back so it doesn't sit claimed until it expires
+                let (mode, claim_data) = claimed;
+                error!(
+                    id = claim_data.id,
+                    reason = panic_message(&payload),
+                    "processing failed, releasing field"
+                );
+                let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    client.block_on(release_field(config, client, &mode, &claim_data))
+                }));
+                summary.fields_failed += 1;
+            }
+        }
+    }
+
+    // stop claiming and hand back anything we won't get to
+    stop_claiming.trigger();
+    claim_receiver.close();
+    while let Some((mode, claim_data, _, _)) = client.block_on(claim_receiver.recv()) {
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            client.block_on(release_field(config, client, &mode, &claim_data))
+        }));
+    }
+
+    // the background tasks catch their own failures, and finish once they run out of work
+    drop(result_sender);
+    if let Err(e) = client.block_on(claimer) {
+        error!(reason = join_error_message(e), "claimer failed");
+    }
+    if let Err(e) = client.block_on(submitter) {
+        error!(reason = join_error_message(e), "submitter failed");
+    }
+    for submitted in submitted_receiver.try_iter() {
+        match submitted {
+            Ok(result) => {
+                summary.record(&result);
+                summary.last_outcome = Some(RunOutcome::Completed(result));
+            }
+            Err(_) => summary.fields_failed += 1,
+        }
+    }
+    summary.fields_failed += claim_failures.load(Ordering::SeqCst);
+    summary.elapsed = started.elapsed();
+    summary
+}
+
+/// Parse a duration like `90`, `90s`, `15m`, `12h` or `7d`. Bare numbers are seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, multiplier) = match input.chars().last() {
+        Some('s') => (&input[..input.len() - 1], 1),
+        Some('m') => (&input[..input.len() - 1], 60),
+        Some('h') => (&input[..input.len() - 1], 60 * 60),
+        Some('d') => (&input[..input.len() - 1], 24 * 60 * 60),
+        _ => (input, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration: {}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(43200)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(604800)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("-3s").is_err());
+    }
+
+    #[test]
+    fn test_run_session_max_fields() {
+        let config = Config {
+            benchmark: true,
+            base: Some(10),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_fields: Some(3),
+            ..Default::default()
+        };
+        let summary = run_session(&config, &client, &limits, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 3);
+        assert_eq!(summary.fields_failed, 0);
+        assert_eq!(summary.numbers_checked, Natural::from(3 * 53_u32));
+        assert_eq!(summary.near_misses, 3);
+        assert_eq!(summary.nice_numbers, vec!["69", "69", "69"]);
+    }
+
+    #[test]
+    fn test_run_pipelined_session() {
+        let config = Config {
+            benchmark: true,
+            base: Some(10),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_fields: Some(5),
+            ..Default::default()
+        };
+        for depth in 1..4 {
+            let pipeline = PipelineOptions {
+                depth,
+                claim_expiry: Duration::from_secs(3600),
+            };
+            let summary =
+                run_pipelined_session(&config, &client, &limits, &pipeline, &ShutdownSignal::new());
+            assert_eq!(summary.fields_completed, 5);
+            assert_eq!(summary.fields_failed, 0);
+            assert_eq!(summary.numbers_checked, Natural::from(5 * 53_u32));
+            assert_eq!(summary.nice_numbers.len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_run_pipelined_session_expiry() {
+        let config = Config {
+            benchmark: true,
+            base: Some(10),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_duration: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let pipeline = PipelineOptions {
+            depth: 1,
+            claim_expiry: Duration::ZERO,
+        };
+        let summary =
+            run_pipelined_session(&config, &client, &limits, &pipeline, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 0);
+        assert!(summary.fields_expired > 0);
+    }
+
+    #[test]
+    fn test_estimate_processing_time() {
+        let claim_data = get_field_benchmark(Some(40), Some(150000), &PowerSet::SQUARE_CUBE);
+        let start = &claim_data.search_start;
+        assert_eq!(
+            estimate_processing_time(&claim_data, start, 0.0),
+            Duration::ZERO
+        );
+        assert_eq!(
+            estimate_processing_time(&claim_data, start, 50000.0),
+            Duration::from_secs(3)
+        );
+        let next = start + Natural::from(100000_u32);
+        assert_eq!(
+            estimate_processing_time(&claim_data, &next, 50000.0),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_run_pipelined_session_panic_release() {
+        // a checkpoint with a damaged near miss resumes fine, but can't be merged with the rest
+        let path = env::temp_dir().join(format!("nice-panic-{}.json", std::process::id()));
+        let near_misses = NearMissOptions {
+            top_k: Some(3),
+            ..Default::default()
+        };
+        let mut claim_data = get_field_benchmark(Some(40), Some(150000), &PowerSet::SQUARE_CUBE);
+        claim_data.id = 12;
+        let next = &claim_data.search_start + Natural::from(partial::STEP_SIZE);
+        let mut submit_data = process_field(
+            &Mode::Detailed,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &near_misses,
+            &PowerSet::SQUARE_CUBE,
+            &get_sub_claim(&claim_data, &claim_data.search_start, &next),
+        );
+        submit_data.top_near_misses = Some(vec![("damaged".to_string(), 30)]);
+        shutdown::save_checkpoint(
+            &path,
+            &Mode::Detailed,
+            &claim_data,
+            Some((submit_data, next)),
+        )
+        .unwrap();
+
+        // the claimer's next claim fails whichever of it and the release gets the empty reply
+        let (api_base, server) = api_common::serve_mock_api(vec![
+            ("200 OK", serde_json::to_string(&claim_data).unwrap()),
+            ("200 OK", "{}".to_string()),
+            ("200 OK", "{}".to_string()),
+        ]);
+        let config = Config {
+            near_misses,
+            checkpoint_file: path,
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions {
+            api_base,
+            ..Default::default()
+        })
+        .unwrap();
+        let limits = SessionLimits {
+            max_duration: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let pipeline = PipelineOptions {
+            depth: 1,
+            claim_expiry: Duration::from_secs(3600),
+        };
+        let summary =
+            run_pipelined_session(&config, &client, &limits, &pipeline, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 0);
+        assert!(summary.fields_failed >= 2);
+        let requests = server.join().unwrap();
+        assert!(requests
+            .iter()
+            .any(|request| request.starts_with("POST /api/release/detailed ")
+                && request.contains("\"id\":12")));
+    }
+
+    #[test]
+    fn test_run_pipelined_session_claim_backoff() {
+        // every claim fails, so the claimer is backing off when the time limit is hit
+        let config = Config {
+            benchmark: true,
+            base: Some(11),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_duration: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let pipeline = PipelineOptions {
+            depth: 1,
+            claim_expiry: Duration::from_secs(3600),
+        };
+        let summary =
+            run_pipelined_session(&config, &client, &limits, &pipeline, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 0);
+        assert!(summary.fields_failed > 0);
+        assert!(summary.elapsed < SESSION_RETRY_DELAY_MIN);
+    }
+
+    #[test]
+    fn test_run_session_rejected() {
+        // base 98 doesn't fit in the integer engine
+        let config = Config {
+            benchmark: true,
+            base: Some(98),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_consecutive_failures: Some(1),
+            ..Default::default()
+        };
+        let summary = run_session(&config, &client, &limits, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 0);
+        assert_eq!(summary.fields_failed, 1);
+        assert!(matches!(
+            summary.last_outcome,
+            Some(RunOutcome::Rejected { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_session_failure() {
+        let config = Config {
+            benchmark: true,
+            base: Some(11),
+            ..Default::default()
+        };
+        let client = ApiClient::new(&ApiOptions::default()).unwrap();
+        let limits = SessionLimits {
+            max_consecutive_failures: Some(1),
+            ..Default::default()
+        };
+        let summary = run_session(&config, &client, &limits, &ShutdownSignal::new());
+        assert_eq!(summary.fields_completed, 0);
+        assert_eq!(summary.fields_failed, 1);
+    }
+}
@@ -0,0 +1,425 @@
+//! A module for handling termination signals (SIGINT/SIGTERM).
+
+use super::*;
+
+/// What to do with the current field when a termination signal arrives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ShutdownAction {
+    /// Finish processing the current field and submit it before exiting.
+    Finish,
+    /// Save the current claim to the checkpoint file so it can be reclaimed on the next start.
+    Checkpoint,
+    /// Release the current claim back to the server immediately.
+    Release,
+    /// Stop between steps, submit the part of the current field that was processed, and release the rest.
+    Submit,
+}
+
+/// A flag shared between the signal handler and the processing loop.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+impl ShutdownSignal {
+    /// Create a signal that is never triggered by the OS. Useful for library users and tests.
+    pub fn new() -> Self {
+        ShutdownSignal(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Register handlers for SIGINT and SIGTERM.
+    /// The first signal asks the client to stop, a second one exits immediately.
+    pub fn install() -> Self {
+        let signal = ShutdownSignal::new();
+        let handler_signal = signal.clone();
+        ctrlc::set_handler(move || {
+            if handler_signal.is_triggered() {
+                warn!("received second termination signal, exiting immediately");
+                std::process::exit(130);
+            }
+            warn!("received termination signal, shutting down");
+            handler_signal.trigger();
+        })
+        .unwrap_or_else(|e| panic!("Error setting signal handler: {}", e));
+        signal
+    }
+
+    /// Ask the processing loop to stop.
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Check whether a shutdown has been requested.
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A claim that was interrupted and saved locally.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub mode: Mode,
+    pub claim: FieldClaim,
+    /// The results so far, if any numbers were processed.
+    #[serde(default)]
+    pub progress: Option<CheckpointProgress>,
+}
+
+/// The results for the start of a field, up to (but not including) `next`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckpointProgress {
+    pub submit_data: FieldSubmit,
+    #[serde(
+        serialize_with = "serialize_natural_to_string",
+        deserialize_with = "deserialize_string_to_natural"
+    )]
+    pub next: Natural,
+}
+
+/// Save the current claim and its results so far, so it can be picked up again on the next start.
+pub fn save_checkpoint(
+    path: &Path,
+    mode: &Mode,
+    claim_data: &FieldClaim,
+    progress: Option<PartialProgress>,
+) -> Result<(), String> {
+    let checkpoint = Checkpoint {
+        mode: *mode,
+        claim: claim_data.clone(),
+        progress: progress.map(|(submit_data, next)| CheckpointProgress { submit_data, next }),
+    };
+    let contents = serde_json::to_string_pretty(&checkpoint)
+        .map_err(|e| format!("Error serializing checkpoint: {}", e))?;
+    fs::write(path, contents)
+        .map_err(|e| format!("Error writing checkpoint {}: {}", path.display(), e))
+}
+
+/// Load a saved checkpoint, if there is one. It stays on disk until `remove_checkpoint`.
+pub fn load_checkpoint(path: &Path) -> Result<Option<Checkpoint>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(format!(
+                "Error reading checkpoint {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| format!("Error reading checkpoint {}: {}", path.display(), e))
+}
+
+/// Remove the saved checkpoint once its field has been reclaimed, or can't be.
+pub fn remove_checkpoint(path: &Path) -> Result<(), String> {
+    fs::remove_file(path)
+        .map_err(|e| format!("Error removing checkpoint {}: {}", path.display(), e))
+}
+
+/// Deal with a field we were asked to stop before finishing, as `config.on_shutdown` says.
+/// `progress` is the results so far and where they end, if any numbers were processed.
+pub fn interrupt_field(
+    config: &Config,
+    client: &ApiClient,
+    mode: &Mode,
+    claim_data: &FieldClaim,
+    progress: Option<PartialProgress>,
+    elapsed: Duration,
+) -> RunOutcome {
+    let id = claim_data.id;
+    match (config.on_shutdown, progress) {
+        _ if config.benchmark => RunOutcome::Abandoned { id },
+        (ShutdownAction::Checkpoint, progress) => {
+            match save_checkpoint(&config.checkpoint_file, mode, claim_data, progress) {
+                Ok(()) => RunOutcome::Checkpointed { id },
+                // don't hold on to a field nobody will come back for
+                Err(e) => {
+                    error!(id, error = %e, "could not save checkpoint, releasing field");
+                    client.block_on(client.release_field(mode, claim_data));
+                    RunOutcome::Released { id }
+                }
+            }
+        }
+        (ShutdownAction::Submit, Some((submit_data, processed_end))) => {
+            let processed = get_sub_claim(claim_data, &claim_data.search_start, &processed_end);
+            submit_partial_field(
+                config,
+                client,
+                mode,
+                claim_data,
+                processed,
+                submit_data,
+                elapsed,
+            )
+        }
+        _ => {
+            client.block_on(client.release_field(mode, claim_data));
+            RunOutcome::Released { id }
+        }
+    }
+}
+
+/// Submit the processed start of an interrupted field, so the server can reassign the rest.
+/// The processed part is verified first if enabled, and if that fails or the server won't take
+/// partial results, the whole field is released without submitting anything.
+fn submit_partial_field(
+    config: &Config,
+    client: &ApiClient,
+    mode: &Mode,
+    claim_data: &FieldClaim,
+    processed: FieldClaim,
+    mut submit_data: FieldSubmit,
+    elapsed: Duration,
+) -> RunOutcome {
+    let id = claim_data.id;
+    if config.verify {
+        if let Err(reason) = verify_field(
+            mode,
+            &processed,
+            &submit_data,
+            &config.near_misses.cutoff,
+            &config.powers,
+            config.verify_sample,
+        ) {
+            error!(id, %reason, "verification failed");
+            client.block_on(client.release_field(mode, claim_data));
+            return RunOutcome::Rejected { id, reason };
+        }
+    }
+    info!(id, processed_end = %processed.search_end, "submitting processed part of field");
+    submit_data.processed_end = Some(processed.search_end.to_string());
+    if let Err(e) = client.block_on(client.submit_partial_field(mode, &submit_data)) {
+        warn!(id, error = %e, "server didn't accept partial results, releasing the whole field");
+        client.block_on(client.release_field(mode, claim_data));
+        return RunOutcome::Released { id };
+    }
+    let result = FieldResult {
+        mode: *mode,
+        claim_data: processed,
+        submit_data,
+        elapsed,
+    };
+    config.metrics.record_result(&result);
+    save_result(config, &result);
+    RunOutcome::PartiallySubmitted(Box::new(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_signal() {
+        let signal = ShutdownSignal::new();
+        let other = signal.clone();
+        assert!(!signal.is_triggered());
+        other.trigger();
+        assert!(signal.is_triggered());
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let path = env::temp_dir().join(format!("nice-checkpoint-{}.json", std::process::id()));
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        let next = Natural::from(60_u32);
+        let submit_data = process_field(
+            &Mode::Niceonly,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &NearMissOptions::default(),
+            &PowerSet::SQUARE_CUBE,
+            &get_sub_claim(&claim_data, &claim_data.search_start, &next),
+        );
+        let progress = Some((submit_data.clone(), next.clone()));
+        save_checkpoint(&path, &Mode::Niceonly, &claim_data, progress).unwrap();
+
+        let checkpoint = load_checkpoint(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.mode, Mode::Niceonly);
+        assert_eq!(checkpoint.claim.id, claim_data.id);
+        assert_eq!(checkpoint.claim.search_start, claim_data.search_start);
+        assert_eq!(checkpoint.claim.search_end, claim_data.search_end);
+        assert_eq!(checkpoint.claim.search_range, claim_data.search_range);
+        let progress = checkpoint.progress.unwrap();
+        assert_eq!(progress.submit_data, submit_data);
+        assert_eq!(progress.next, next);
+
+        // the checkpoint stays until it's removed
+        assert!(load_checkpoint(&path).unwrap().is_some());
+        remove_checkpoint(&path).unwrap();
+        assert!(load_checkpoint(&path).unwrap().is_none());
+        assert!(remove_checkpoint(&path).is_err());
+
+        // a damaged checkpoint is an error, not a panic
+        fs::write(&path, "{\"mode\":").unwrap();
+        assert!(load_checkpoint(&path).is_err());
+        remove_checkpoint(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let path = env::temp_dir().join(format!("nice-resume-{}.json", std::process::id()));
+        let config = Config {
+            on_shutdown: ShutdownAction::Checkpoint,
+            checkpoint_file: path.clone(),
+            ..Default::default()
+        };
+        let mut claim_data = get_field_benchmark(Some(40), Some(150000), &PowerSet::SQUARE_CUBE);
+        claim_data.id = 12;
+        let claim_json = serde_json::to_string(&claim_data).unwrap();
+        let get_client = |api_base: String| {
+            ApiClient::new(&ApiOptions {
+                api_base,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let expected = process_field(
+            &Mode::Detailed,
+            false,
+            false,
+            config.grain_size,
+            &config.near_misses,
+            &config.powers,
+            &claim_data,
+        );
+
+        // interrupt the field partway through, keeping the results so far
+        let next = &claim_data.search_start + Natural::from(partial::STEP_SIZE);
+        let submit_data = process_field(
+            &Mode::Detailed,
+            false,
+            false,
+            config.grain_size,
+            &config.near_misses,
+            &config.powers,
+            &get_sub_claim(&claim_data, &claim_data.search_start, &next),
+        );
+        let (api_base, server) = api_common::serve_mock_api(Vec::new());
+        let outcome = interrupt_field(
+            &config,
+            &get_client(api_base),
+            &Mode::Detailed,
+            &claim_data,
+            Some((submit_data, next.clone())),
+            Duration::from_secs(1),
+        );
+        assert!(matches!(outcome, RunOutcome::Checkpointed { id: 12 }));
+        assert!(server.join().unwrap().is_empty());
+
+        // pick it up again and finish the rest
+        let (api_base, server) = api_common::serve_mock_api(vec![("200 OK", claim_json.clone())]);
+        let client = get_client(api_base);
+        let (mode, reclaimed, progress) = client.block_on(claim_field(&config, &client));
+        assert!(server.join().unwrap()[0].contains("field=12"));
+        assert!(load_checkpoint(&path).unwrap().is_none());
+        assert_eq!(progress.as_ref().map(|(_, next)| next), Some(&next));
+        let outcome = process_claimed_field(
+            &config,
+            &client,
+            &ShutdownSignal::new(),
+            mode,
+            reclaimed,
+            progress,
+        );
+        match outcome {
+            RunOutcome::Completed(result) => assert_eq!(result.submit_data, expected),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // a claim the server won't give back is dropped for a fresh one
+        save_checkpoint(&path, &Mode::Detailed, &claim_data, None).unwrap();
+        let (api_base, server) = api_common::serve_mock_api(vec![
+            ("404 Not Found", String::new()),
+            ("200 OK", claim_json.clone()),
+        ]);
+        let client = get_client(api_base);
+        let (_, _, progress) = client.block_on(claim_field(&config, &client));
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("field=12"));
+        assert!(!requests[1].contains("field="));
+        assert!(progress.is_none());
+        assert!(load_checkpoint(&path).unwrap().is_none());
+
+        // and so is a damaged checkpoint
+        fs::write(&path, "{\"mode\":").unwrap();
+        let (api_base, server) = api_common::serve_mock_api(vec![("200 OK", claim_json)]);
+        let client = get_client(api_base);
+        client.block_on(claim_field(&config, &client));
+        assert!(!server.join().unwrap()[0].contains("field="));
+        let _ = remove_checkpoint(&path);
+    }
+
+    #[test]
+    fn test_interrupt_field_submit() {
+        let claim_data = get_field_benchmark(Some(40), Some(150000), &PowerSet::SQUARE_CUBE);
+        let processed_end = &claim_data.search_start + Natural::from(partial::STEP_SIZE);
+        let submit_data = process_field(
+            &Mode::Niceonly,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &NearMissOptions::default(),
+            &PowerSet::SQUARE_CUBE,
+            &get_sub_claim(&claim_data, &claim_data.search_start, &processed_end),
+        );
+        let interrupt = |api_base: String| {
+            let config = Config {
+                on_shutdown: ShutdownAction::Submit,
+                verify: true,
+                ..Default::default()
+            };
+            let client = ApiClient::new(&ApiOptions {
+                api_base,
+                ..Default::default()
+            })
+            .unwrap();
+            let progress = Some((submit_data.clone(), processed_end.clone()));
+            let outcome = interrupt_field(
+                &config,
+                &client,
+                &Mode::Niceonly,
+                &claim_data,
+                progress,
+                Duration::from_secs(1),
+            );
+            (outcome, config.metrics)
+        };
+
+        // only the processed part is submitted, and it's recorded as such
+        let (api_base, server) = api_common::serve_mock_api(vec![("200 OK", String::new())]);
+        let (outcome, metrics) = interrupt(api_base);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("POST /api/submit/niceonly/partial "));
+        assert!(requests[0].contains(&format!("\"processed_end\":\"{}\"", processed_end)));
+        match outcome {
+            RunOutcome::PartiallySubmitted(result) => {
+                assert_eq!(result.claim_data.search_end, processed_end);
+                assert_eq!(
+                    result.claim_data.search_range,
+                    Natural::from(partial::STEP_SIZE)
+                );
+            }
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+        let body = metrics.render();
+        assert!(body.contains("\nnice_fields_completed_total 0\n"));
+        assert!(body.contains(&format!(
+            "\nnice_numbers_processed_total {}\n",
+            partial::STEP_SIZE
+        )));
+
+        // a server that doesn't know about partial results only gets the field back
+        let (api_base, server) = api_common::serve_mock_api(vec![
+            ("404 Not Found", String::new()),
+            ("200 OK", String::new()),
+        ]);
+        let (outcome, _) = interrupt(api_base);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("POST /api/submit/niceonly/partial "));
+        assert!(requests[1].starts_with("POST /api/release/niceonly "));
+        assert!(matches!(outcome, RunOutcome::Released { id } if id == claim_data.id));
+    }
+}
@@ -0,0 +1,472 @@
+//! A module for double-checking results before they are submitted.
+//! Everything here is deliberately slow and shares no code with the processing engines,
+//! so a hardware or engine fault is unlikely to produce the same wrong answer twice.
+
+use super::*;
+
+/// Count the unique digits of n^2 and n^3 (or other powers) using the library digit conversion.
+fn get_num_uniques_slow(num: &Natural, base: u32, powers: &PowerSet) -> u32 {
+    let mut digits_indicator = vec![false; base as usize];
+    for digit in num.pow(powers.low as u64).to_digits_asc(&base) {
+        digits_indicator[digit as usize] = true;
+    }
+    for digit in num.pow(powers.high as u64).to_digits_asc(&base) {
+        digits_indicator[digit as usize] = true;
+    }
+    digits_indicator.iter().filter(|&&x| x).count() as u32
+}
+
+/// Find the missing and repeated digits of n^2 and n^3 (or other powers) using the library digit conversion.
+fn get_near_miss_detail_slow(num: &Natural, base: u32, powers: &PowerSet) -> NearMissDetail {
+    let mut digit_counts = vec![0_u16; base as usize];
+    for digit in num.pow(powers.low as u64).to_digits_asc(&base) {
+        digit_counts[digit as usize] += 1;
+    }
+    for digit in num.pow(powers.high as u64).to_digits_asc(&base) {
+        digit_counts[digit as usize] += 1;
+    }
+    NearMissDetail::from_digit_counts(&digit_counts)
+}
+
+/// Parse a number reported in the results.
+fn parse_reported(num: &str) -> Result<Natural, String> {
+    Natural::from_str(num).map_err(|_| format!("reported number {} is not a number", num))
+}
+
+/// Check that a reported number is actually inside the claimed range.
+fn check_in_range(num: &Natural, claim_data: &FieldClaim) -> Result<(), String> {
+    if num < &claim_data.search_start || num >= &claim_data.search_end {
+        return Err(format!("reported number {} is outside the field", num));
+    }
+    Ok(())
+}
+
+/// Re-check a processed field: every reported number, the histogram total,
+/// and a random sample of `sample_size` numbers from the range.
+pub fn verify_field(
+    mode: &Mode,
+    claim_data: &FieldClaim,
+    submit_data: &FieldSubmit,
+    near_miss_cutoff: &NearMissCutoff,
+    powers: &PowerSet,
+    sample_size: u32,
+) -> Result<(), String> {
+    let base = claim_data.base;
+    let mut near_misses_cutoff = near_miss_cutoff.get_threshold(base);
+    let top_k = matches!(near_miss_cutoff, NearMissCutoff::TopK(_));
+    let empty_near_misses = HashMap::new();
+    let empty_nice_list = Vec::new();
+    let near_misses = submit_data
+        .near_misses
+        .as_ref()
+        .unwrap_or(&empty_near_misses);
+    let nice_list = submit_data.nice_list.as_ref().unwrap_or(&empty_nice_list);
+
+    // the histogram must account for every number in the range
+    if let Mode::Detailed = mode {
+        let unique_count = submit_data
+            .unique_count
+            .as_ref()
+            .ok_or("detailed results are missing the unique count")?;
+        let total = unique_count
+            .values()
+            .fold(Natural::ZERO, |acc, &x| acc + Natural::from(x));
+        if total != claim_data.search_range {
+            return Err(format!(
+                "unique counts sum to {} but the field has {} numbers",
+                total, claim_data.search_range
+            ));
+        }
+    }
+
+    // with top-K, anything better than the worst kept number must have been reported
+    if let NearMissCutoff::TopK(k) = *near_miss_cutoff {
+        let expected = u64::try_from(&claim_data.search_range).map_or(k, |r| k.min(r as usize));
+        if near_misses.len() != expected {
+            return Err(format!(
+                "{} near misses were reported but top {} were requested",
+                near_misses.len(),
+                expected
+            ));
+        }
+        near_misses_cutoff = near_misses.values().min().map_or(0, |&worst| worst);
+    }
+
+    // every reported number must be exactly what was claimed
+    for (num, &uniques) in near_misses {
+        let num = parse_reported(num)?;
+        check_in_range(&num, claim_data)?;
+        let actual = get_num_uniques_slow(&num, base, powers);
+        if actual != uniques || (!top_k && actual <= near_misses_cutoff) {
+            return Err(format!(
+                "near miss {} was reported with {} uniques but has {}",
+                num, uniques, actual
+            ));
+        }
+    }
+    let empty_top = Vec::new();
+    let top_near_misses = submit_data.top_near_misses.as_ref().unwrap_or(&empty_top);
+    for (num, uniques) in top_near_misses {
+        let num = parse_reported(num)?;
+        check_in_range(&num, claim_data)?;
+        let actual = get_num_uniques_slow(&num, base, powers);
+        if actual != *uniques {
+            return Err(format!(
+                "top near miss {} was reported with {} uniques but has {}",
+                num, uniques, actual
+            ));
+        }
+    }
+    if top_near_misses.windows(2).any(|w| w[0].1 < w[1].1) {
+        return Err("top near misses are out of order".to_string());
+    }
+    if let Some(near_miss_details) = &submit_data.near_miss_details {
+        for (num, detail) in near_miss_details {
+            let num = parse_reported(num)?;
+            check_in_range(&num, claim_data)?;
+            if *detail != get_near_miss_detail_slow(&num, base, powers) {
+                return Err(format!(
+                    "near miss {} was reported with the wrong digits",
+                    num
+                ));
+            }
+        }
+    }
+    let top_worst = top_near_misses.last().map(|&(_, uniques)| uniques);
+    for num in nice_list {
+        let num = parse_reported(num)?;
+        check_in_range(&num, claim_data)?;
+        let actual = get_num_uniques_slow(&num, base, powers);
+        if actual != base {
+            return Err(format!(
+                "nice number {} was reported but only has {} uniques",
+                num, actual
+            ));
+        }
+    }
+
+    // and nothing in a random sample should have been missed
+    let range = u128::try_from(&claim_data.search_range)
+        .map_err(|_| "field is too large to sample".to_string())?;
+    if range == 0 {
+        return Ok(());
+    }
+    let mut rng = rand::thread_rng();
+    for _ in 0..sample_size {
+        let num = &claim_data.search_start + Natural::from(rng.gen_range(0..range));
+        let uniques = get_num_uniques_slow(&num, base, powers);
+        let reported = match mode {
+            Mode::Detailed => near_misses.get(&num.to_string()).copied(),
+            Mode::Niceonly => nice_list.contains(&num.to_string()).then_some(base),
+        };
+        let expected = match mode {
+            // ties with the worst kept number may or may not have made the cut
+            Mode::Detailed if top_k && uniques == near_misses_cutoff => reported,
+            Mode::Detailed => (uniques > near_misses_cutoff).then_some(uniques),
+            Mode::Niceonly => (uniques == base).then_some(base),
+        };
+        if reported != expected {
+            return Err(format!(
+                "sampled number {} has {} uniques but was reported as {:?}",
+                num, uniques, reported
+            ));
+        }
+        if top_worst.is_some_and(|worst| uniques > worst)
+            && !top_near_misses.iter().any(|(n, _)| *n == num.to_string())
+        {
+            return Err(format!(
+                "sampled number {} has {} uniques but is missing from the top near misses",
+                num, uniques
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Process the base 10 benchmark field without parallelism.
+    fn process_b10(mode: Mode, near_miss_options: NearMissOptions) -> FieldSubmit {
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        process_field(
+            &mode,
+            false,
+            false,
+            process_integer::DEFAULT_GRAIN_SIZE,
+            &near_miss_options,
+            &PowerSet::SQUARE_CUBE,
+            &claim_data,
+        )
+    }
+
+    #[test]
+    fn test_get_num_uniques_slow() {
+        let sqube = PowerSet::SQUARE_CUBE;
+        assert_eq!(get_num_uniques_slow(&Natural::from(69_u32), 10, &sqube), 10);
+        assert_eq!(get_num_uniques_slow(&Natural::from(47_u32), 10, &sqube), 6);
+        let claim_data = FieldClaim {
+            id: 0,
+            username: "test".to_owned(),
+            base: 10,
+            search_start: Natural::from(47_u32),
+            search_end: Natural::from(100_u32),
+            search_range: Natural::from(53_u32),
+        };
+        for powers in [sqube, PowerSet::new(2, 4).unwrap()] {
+            let context = BaseContext::new(&claim_data, &powers, &NearMissCutoff::default());
+            for num in 47..100 {
+                assert_eq!(
+                    get_num_uniques_slow(&Natural::from(num as u32), 10, &powers),
+                    process_integer::get_num_uniques(num, &context)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_field() {
+        let cutoff = NearMissCutoff::default();
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        for mode in [Mode::Detailed, Mode::Niceonly] {
+            let submit_data = process_b10(mode, NearMissOptions::default());
+            assert_eq!(
+                verify_field(
+                    &mode,
+                    &claim_data,
+                    &submit_data,
+                    &cutoff,
+                    &PowerSet::SQUARE_CUBE,
+                    100
+                ),
+                Ok(())
+            );
+        }
+
+        // wrong histogram
+        let mut submit_data = process_b10(Mode::Detailed, NearMissOptions::default());
+        *submit_data
+            .unique_count
+            .as_mut()
+            .unwrap()
+            .get_mut(&7)
+            .unwrap() += 1;
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+
+        // wrong near miss
+        let mut submit_data = process_b10(Mode::Detailed, NearMissOptions::default());
+        submit_data.near_misses = Some(HashMap::from([("68".to_string(), 10)]));
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+
+        // missing near miss, caught by sampling everything
+        let mut submit_data = process_b10(Mode::Detailed, NearMissOptions::default());
+        submit_data.near_misses = Some(HashMap::new());
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            1000
+        )
+        .is_err());
+
+        // wrong nice number
+        let mut submit_data = process_b10(Mode::Niceonly, NearMissOptions::default());
+        submit_data.nice_list = Some(vec!["70".to_string()]);
+        assert!(verify_field(
+            &Mode::Niceonly,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+
+        // nice number outside the field
+        submit_data.nice_list = Some(vec!["69".to_string(), "146".to_string()]);
+        assert!(verify_field(
+            &Mode::Niceonly,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_field_cutoffs() {
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        for cutoff in [
+            NearMissCutoff::Absolute(7),
+            NearMissCutoff::Fraction(1, 2),
+            NearMissCutoff::TopK(5),
+            NearMissCutoff::TopK(100),
+        ] {
+            let submit_data = process_b10(
+                Mode::Detailed,
+                NearMissOptions {
+                    cutoff,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                verify_field(
+                    &Mode::Detailed,
+                    &claim_data,
+                    &submit_data,
+                    &cutoff,
+                    &PowerSet::SQUARE_CUBE,
+                    1000
+                ),
+                Ok(())
+            );
+        }
+
+        // results for a different cutoff don't pass
+        let cutoff = NearMissCutoff::TopK(5);
+        let submit_data = process_b10(
+            Mode::Detailed,
+            NearMissOptions {
+                cutoff: NearMissCutoff::TopK(4),
+                ..Default::default()
+            },
+        );
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+        let submit_data = process_b10(
+            Mode::Detailed,
+            NearMissOptions {
+                cutoff: NearMissCutoff::Absolute(8),
+                ..Default::default()
+            },
+        );
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &submit_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_field_top_near_misses() {
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        let submit_data = process_b10(
+            Mode::Detailed,
+            NearMissOptions {
+                top_k: Some(5),
+                ..Default::default()
+            },
+        );
+        let cutoff = NearMissCutoff::default();
+        assert_eq!(
+            verify_field(
+                &Mode::Detailed,
+                &claim_data,
+                &submit_data,
+                &cutoff,
+                &PowerSet::SQUARE_CUBE,
+                1000
+            ),
+            Ok(())
+        );
+
+        // wrong count
+        let mut bad_data = submit_data.clone();
+        bad_data.top_near_misses.as_mut().unwrap()[1].1 += 1;
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &bad_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+
+        // a better number left out, caught by sampling everything
+        let mut bad_data = submit_data.clone();
+        bad_data.top_near_misses.as_mut().unwrap().remove(0);
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &bad_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            1000
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_field_near_miss_details() {
+        let claim_data = get_field_benchmark(Some(10), None, &PowerSet::SQUARE_CUBE);
+        let cutoff = NearMissCutoff::Absolute(8);
+        let submit_data = process_b10(
+            Mode::Detailed,
+            NearMissOptions {
+                cutoff,
+                top_k: None,
+                details: true,
+            },
+        );
+        assert_eq!(submit_data.near_miss_details.as_ref().unwrap().len(), 2);
+        assert_eq!(
+            verify_field(
+                &Mode::Detailed,
+                &claim_data,
+                &submit_data,
+                &cutoff,
+                &PowerSet::SQUARE_CUBE,
+                100
+            ),
+            Ok(())
+        );
+
+        // wrong digits
+        let mut bad_data = submit_data.clone();
+        for detail in bad_data.near_miss_details.as_mut().unwrap().values_mut() {
+            detail.missing.push(0);
+        }
+        assert!(verify_field(
+            &Mode::Detailed,
+            &claim_data,
+            &bad_data,
+            &cutoff,
+            &PowerSet::SQUARE_CUBE,
+            0
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! Property tests checking that every engine agrees with each other
+//! and with a naive reference implementation on random fields.
+
+extern crate malachite;
+extern crate nice_rust;
+extern crate proptest;
+
+use malachite::natural::Natural;
+use malachite::num::arithmetic::traits::Pow;
+use malachite::num::conversion::traits::Digits;
+use nice_rust::{
+    process_integer, process_limbs, process_natural, BaseContext, FieldClaim, FieldSubmit,
+    NearMissCutoff, NearMissDetail, NearMissOptions, PowerSet,
+};
+use proptest::prelude::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const MAX_FIELD_SIZE: u64 = 200;
+/// Small enough that parallel runs split every field into several chunks.
+const GRAIN_SIZE: usize = 16;
+
+const SQUARE_CUBE: PowerSet = PowerSet::SQUARE_CUBE;
+
+/// Count unique digits in the simplest way possible.
+fn naive_num_uniques(num: &Natural, base: u32, powers: &PowerSet) -> u32 {
+    let mut digits: Vec<u32> = num.pow(powers.low as u64).to_digits_asc(&base);
+    digits.extend(num.pow(powers.high as u64).to_digits_asc(&base));
+    digits.sort();
+    digits.dedup();
+    digits.len() as u32
+}
+
+/// Find the missing and repeated digits in the simplest way possible.
+fn naive_near_miss_detail(num: &Natural, base: u32) -> NearMissDetail {
+    let mut digits: Vec<u32> = num.pow(2).to_digits_asc(&base);
+    digits.extend(num.pow(3).to_digits_asc(&base));
+    NearMissDetail {
+        missing: (0..base).filter(|d| !digits.contains(d)).collect(),
+        duplicated: (0..base)
+            .map(|d| (d, digits.iter().filter(|&&x| x == d).count() as u32))
+            .filter(|&(_, count)| count > 1)
+            .collect(),
+    }
+}
+
+/// Process a field in the simplest way possible.
+fn naive_process(
+    claim_data: &FieldClaim,
+    detailed: bool,
+    near_miss_cutoff: &NearMissCutoff,
+    powers: &PowerSet,
+) -> FieldSubmit {
+    let base = claim_data.base;
+    let mut unique_count: HashMap<u32, u32> = (1..=base).map(|i| (i, 0)).collect();
+    let mut all_uniques = Vec::new();
+    let mut nice_list = Vec::new();
+
+    let mut num = claim_data.search_start.clone();
+    while num < claim_data.search_end {
+        let uniques = naive_num_uniques(&num, base, powers);
+        *unique_count.get_mut(&uniques).unwrap() += 1;
+        if uniques == base {
+            nice_list.push(num.to_string());
+        }
+        all_uniques.push((num.clone(), uniques));
+        num += Natural::from(1_u32);
+    }
+
+    // most uniques first, then smallest number first
+    all_uniques.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let near_misses = match *near_miss_cutoff {
+        NearMissCutoff::Absolute(cutoff) => all_uniques
+            .into_iter()
+            .filter(|&(_, uniques)| uniques > cutoff)
+            .collect::<Vec<_>>(),
+        NearMissCutoff::Fraction(numerator, denominator) => all_uniques
+            .into_iter()
+            .filter(|&(_, uniques)| uniques * denominator > base * numerator)
+            .collect(),
+        NearMissCutoff::TopK(k) => all_uniques.into_iter().take(k).collect(),
+    };
+    let near_misses = near_misses
+        .into_iter()
+        .map(|(num, uniques)| (num.to_string(), uniques))
+        .collect();
+
+    FieldSubmit {
+        id: claim_data.id,
+        username: claim_data.username.clone(),
+        client_version: CLIENT_VERSION.to_string(),
+        unique_count: if detailed { Some(unique_count) } else { None },
+        near_misses: if detailed { Some(near_misses) } else { None },
+        nice_list: if detailed { None } else { Some(nice_list) },
+        top_near_misses: None,
+        near_miss_details: None,
+        processed_end: None,
+    }
+}
+
+prop_compose! {
+    /// Any way of picking near misses, including the default.
+    fn arb_near_miss_cutoff()(
+        absolute in 0_u32..=97,
+        (numerator, denominator) in (1_u32..=100).prop_flat_map(|d| (0..=d, Just(d))),
+        k in 0_usize..=MAX_FIELD_SIZE as usize + 10,
+        kind in 0..4,
+    ) -> NearMissCutoff {
+        match kind {
+            0 => NearMissCutoff::default(),
+            1 => NearMissCutoff::Absolute(absolute),
+            2 => NearMissCutoff::Fraction(numerator, denominator),
+            _ => NearMissCutoff::TopK(k),
+        }
+    }
+}
+
+/// Pick a small field somewhere in a range.
+fn get_field_claim(
+    base: u32,
+    (range_start, range_end): (Natural, Natural),
+    size: u64,
+    position: u64,
+) -> FieldClaim {
+    let size = Natural::from(size).min(&range_end - &range_start);
+    let slack = &range_end - &range_start - &size;
+    let offset = slack * Natural::from(position) / Natural::from(u64::MAX);
+    let search_start = range_start + offset;
+    let search_end = &search_start + &size;
+    FieldClaim {
+        id: 0,
+        username: "differential".to_owned(),
+        base,
+        search_start,
+        search_end,
+        search_range: size,
+    }
+}
+
+prop_compose! {
+    /// A small valid field somewhere in the range of a base the integer engine supports.
+    fn arb_field_claim()(
+        base in (10_u32..=97).prop_filter("base cannot be 1 mod 5", |b| b % 5 != 1),
+        size in 1..=MAX_FIELD_SIZE,
+        position in any::<u64>(),
+    ) -> FieldClaim {
+        get_field_claim(base, nice_rust::get_base_range(base), size, position)
+    }
+}
+
+prop_compose! {
+    /// A small valid field somewhere in the range of a base only the high-base engines support.
+    fn arb_high_base_field_claim()(
+        base in (98_u32..=200).prop_filter("base cannot be 1 mod 5", |b| b % 5 != 1),
+        size in 1..=MAX_FIELD_SIZE,
+        position in any::<u64>(),
+    ) -> FieldClaim {
+        get_field_claim(base, nice_rust::get_base_range(base), size, position)
+    }
+}
+
+prop_compose! {
+    /// A field large enough that the niceonly engines skip parts of it.
+    fn arb_large_field_claim()(
+        base in (10_u32..=60).prop_filter("base cannot be 1 mod 5", |b| b % 5 != 1),
+        size in 1..=MAX_FIELD_SIZE * 50,
+        position in any::<u64>(),
+    ) -> FieldClaim {
+        get_field_claim(base, nice_rust::get_base_range(base), size, position)
+    }
+}
+
+prop_compose! {
+    /// A small field for some other pair of powers, in a base where that pair has candidates.
+    fn arb_power_set_field_claim()(
+        (base, powers) in (
+            10_u32..=60,
+            prop::sample::select(vec![(2, 4), (3, 4), (2, 5), (3, 5), (4, 5)]),
+        )
+            .prop_map(|(base, (low, high))| (base, PowerSet::new(low, high).unwrap()))
+            .prop_filter("range cannot be empty", |(base, powers)| {
+                let (start, end) = nice_rust::get_power_set_range(*base, powers);
+                start < end
+            }),
+        size in 1..=MAX_FIELD_SIZE,
+        position in any::<u64>(),
+    ) -> (FieldClaim, PowerSet) {
+        let range = nice_rust::get_power_set_range(base, &powers);
+        (get_field_claim(base, range, size, position), powers)
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn differential_detailed(claim_data in arb_field_claim()) {
+        let options = NearMissOptions::default();
+        let expected = naive_process(&claim_data, true, &options.cutoff, &SQUARE_CUBE);
+        prop_assert_eq!(&process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), false, GRAIN_SIZE, &options), &expected);
+        prop_assert_eq!(&process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), true, GRAIN_SIZE, &options), &expected);
+        prop_assert_eq!(&process_natural::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), &options), &expected);
+    }
+
+    #[test]
+    fn differential_near_miss_cutoff(
+        claim_data in arb_field_claim(),
+        cutoff in arb_near_miss_cutoff(),
+    ) {
+        let options = NearMissOptions { cutoff, ..Default::default() };
+        let expected = naive_process(&claim_data, true, &cutoff, &SQUARE_CUBE);
+        prop_assert_eq!(&process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), true, GRAIN_SIZE, &options), &expected);
+        prop_assert_eq!(&process_natural::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), &options), &expected);
+    }
+
+    #[test]
+    fn differential_top_near_misses(
+        claim_data in arb_field_claim(),
+        k in 0_usize..=MAX_FIELD_SIZE as usize + 10,
+    ) {
+        let expected = naive_process(&claim_data, true, &NearMissCutoff::TopK(k), &SQUARE_CUBE);
+        let mut expected: Vec<_> = expected.near_misses.unwrap().into_iter().collect();
+        expected.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(Natural::from_str(&a.0).unwrap().cmp(&Natural::from_str(&b.0).unwrap()))
+        });
+        let options = NearMissOptions { top_k: Some(k), ..Default::default() };
+        for submit_data in [
+            process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), false, GRAIN_SIZE, &options),
+            process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), true, GRAIN_SIZE, &options),
+            process_natural::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), &options),
+        ] {
+            prop_assert_eq!(submit_data.top_near_misses.as_ref(), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn differential_near_miss_details(
+        claim_data in arb_field_claim(),
+        cutoff in arb_near_miss_cutoff(),
+        k in 0_usize..=10,
+    ) {
+        let options = NearMissOptions { cutoff, top_k: Some(k), details: true };
+        for submit_data in [
+            process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), true, GRAIN_SIZE, &options),
+            process_natural::process_detailed(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff), &options),
+        ] {
+            let details = submit_data.near_miss_details.unwrap();
+            let mut reported: Vec<&String> = submit_data.near_misses.as_ref().unwrap().keys().collect();
+            reported.extend(submit_data.top_near_misses.as_ref().unwrap().iter().map(|(num, _)| num));
+            reported.sort();
+            reported.dedup();
+            prop_assert_eq!(details.len(), reported.len());
+            for num in reported {
+                let expected = naive_near_miss_detail(&Natural::from_str(num).unwrap(), claim_data.base);
+                prop_assert_eq!(&details[num], &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn differential_niceonly(claim_data in arb_field_claim()) {
+        let expected = naive_process(&claim_data, false, &NearMissCutoff::default(), &SQUARE_CUBE);
+        prop_assert_eq!(&process_integer::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default()), false), &expected);
+        prop_assert_eq!(&process_integer::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default()), true), &expected);
+        prop_assert_eq!(&process_natural::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default())), &expected);
+    }
+
+    #[test]
+    fn differential_niceonly_large(claim_data in arb_large_field_claim()) {
+        let expected = naive_process(&claim_data, false, &NearMissCutoff::default(), &SQUARE_CUBE);
+        prop_assert_eq!(&process_integer::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default()), true), &expected);
+        prop_assert_eq!(&process_natural::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default())), &expected);
+    }
+
+    #[test]
+    fn differential_limbs(claim_data in arb_high_base_field_claim(), k in 0_usize..=10) {
+        let options = NearMissOptions { top_k: Some(k), details: true, ..Default::default() };
+        let context = BaseContext::new(&claim_data, &SQUARE_CUBE, &options.cutoff);
+        let expected = process_natural::process_detailed(&claim_data, &context, &options);
+        prop_assert_eq!(&process_limbs::process_detailed(&claim_data, &context, &options), &expected);
+        let expected = naive_process(&claim_data, false, &NearMissCutoff::default(), &SQUARE_CUBE);
+        prop_assert_eq!(&process_limbs::process_niceonly(&claim_data, &context), &expected);
+    }
+
+    #[test]
+    fn differential_power_sets((claim_data, powers) in arb_power_set_field_claim()) {
+        let options = NearMissOptions::default();
+        let expected = naive_process(&claim_data, true, &options.cutoff, &powers);
+        prop_assert_eq!(&process_integer::process_detailed(&claim_data, &BaseContext::new(&claim_data, &powers, &options.cutoff), true, GRAIN_SIZE, &options), &expected);
+        prop_assert_eq!(&process_natural::process_detailed(&claim_data, &BaseContext::new(&claim_data, &powers, &options.cutoff), &options), &expected);
+        let expected = naive_process(&claim_data, false, &options.cutoff, &powers);
+        prop_assert_eq!(&process_integer::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &powers, &NearMissCutoff::default()), true), &expected);
+        prop_assert_eq!(&process_natural::process_niceonly(&claim_data, &BaseContext::new(&claim_data, &powers, &NearMissCutoff::default())), &expected);
+    }
+}
+
+#[test]
+fn differential_b10_full_range() {
+    // the one base small enough to check exhaustively, and it has a nice number
+    let (search_start, search_end) = nice_rust::get_base_range(10);
+    let claim_data = FieldClaim {
+        id: 0,
+        username: "differential".to_owned(),
+        base: 10,
+        search_range: &search_end - &search_start,
+        search_start,
+        search_end,
+    };
+    let expected = naive_process(&claim_data, false, &NearMissCutoff::default(), &SQUARE_CUBE);
+    assert_eq!(expected.nice_list, Some(vec!["69".to_string()]));
+    assert_eq!(
+        process_integer::process_niceonly(
+            &claim_data,
+            &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default()),
+            true
+        ),
+        expected
+    );
+    assert_eq!(
+        process_natural::process_niceonly(
+            &claim_data,
+            &BaseContext::new(&claim_data, &SQUARE_CUBE, &NearMissCutoff::default())
+        ),
+        expected
+    );
+}
+
+#[test]
+fn differential_power_sets_full_range() {
+    // small ranges with a nice number, to make sure the residue filter keeps them
+    for (base, low, high, nice) in [(10, 3, 4, "18"), (8, 1, 2, "174")] {
+        let powers = PowerSet::new(low, high).unwrap();
+        let (search_start, search_end) = nice_rust::get_power_set_range(base, &powers);
+        let claim_data = FieldClaim {
+            id: 0,
+            username: "differential".to_owned(),
+            base,
+            search_range: &search_end - &search_start,
+            search_start,
+            search_end,
+        };
+        let expected = naive_process(&claim_data, false, &NearMissCutoff::default(), &powers);
+        assert_eq!(expected.nice_list, Some(vec![nice.to_string()]));
+        assert_eq!(
+            process_integer::process_niceonly(
+                &claim_data,
+                &BaseContext::new(&claim_data, &powers, &NearMissCutoff::default()),
+                true
+            ),
+            expected
+        );
+        assert_eq!(
+            process_natural::process_niceonly(
+                &claim_data,
+                &BaseContext::new(&claim_data, &powers, &NearMissCutoff::default())
+            ),
+            expected
+        );
+    }
+}
@@ -1,167 +1,220 @@
 extern crate nice_rust;
 
+fn default_client() -> nice_rust::ApiClient {
+    nice_rust::ApiClient::new(&nice_rust::ApiOptions::default()).unwrap()
+}
+
 #[test]
 fn integration_niceonly_integer_benchmark() {
     nice_rust::run(
-        nice_rust::Mode::Niceonly,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        true,
-        true,
-        false,
-        None,
-        Some(1000000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Niceonly,
+            benchmark: true,
+            parallel: true,
+            range: Some(1000000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 #[test]
 fn integration_niceonly_natural_benchmark() {
     nice_rust::run(
-        nice_rust::Mode::Niceonly,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        true,
-        true,
-        true,
-        None,
-        Some(1000000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Niceonly,
+            benchmark: true,
+            parallel: true,
+            high_bases: true,
+            range: Some(1000000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 
 #[test]
 fn integration_detailed_integer_benchmark() {
     nice_rust::run(
-        nice_rust::Mode::Detailed,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        true,
-        true,
-        false,
-        None,
-        Some(100000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Detailed,
+            benchmark: true,
+            parallel: true,
+            range: Some(100000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 #[test]
 fn integration_detailed_natural_benchmark() {
     nice_rust::run(
-        nice_rust::Mode::Detailed,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        true,
-        true,
-        true,
-        None,
-        Some(100000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Detailed,
+            benchmark: true,
+            parallel: true,
+            high_bases: true,
+            range: Some(100000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 
 #[test]
 fn integration_niceonly_integer_standard() {
     nice_rust::run(
-        nice_rust::Mode::Niceonly,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        false,
-        None,
-        Some(1000000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Niceonly,
+            parallel: true,
+            range: Some(1000000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 #[test]
 fn integration_niceonly_natural_standard() {
     nice_rust::run(
-        nice_rust::Mode::Niceonly,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        true,
-        None,
-        Some(1000000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Niceonly,
+            parallel: true,
+            high_bases: true,
+            range: Some(1000000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 
 #[test]
 fn integration_detailed_integer_standard() {
     nice_rust::run(
-        nice_rust::Mode::Detailed,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        false,
-        None,
-        Some(100000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Detailed,
+            parallel: true,
+            range: Some(100000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 #[test]
 fn integration_detailed_natural_standard() {
     nice_rust::run(
-        nice_rust::Mode::Detailed,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        true,
-        None,
-        Some(100000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Detailed,
+            parallel: true,
+            high_bases: true,
+            range: Some(100000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 
 #[test]
 fn integration_niceonly_natural_b120() {
     nice_rust::run(
-        nice_rust::Mode::Niceonly,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        true,
-        Some(120),
-        Some(1000000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Niceonly,
+            parallel: true,
+            high_bases: true,
+            base: Some(120),
+            range: Some(1000000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
 
 #[test]
 fn integration_detailed_natural_b120() {
     nice_rust::run(
-        nice_rust::Mode::Detailed,
-        "https://nicenumbers.net/api".to_string(),
-        "anonymous".to_string(),
-        false,
-        false,
-        false,
-        true,
-        true,
-        Some(120),
-        Some(100000),
-        None,
+        &nice_rust::Config {
+            mode: nice_rust::Mode::Detailed,
+            parallel: true,
+            high_bases: true,
+            base: Some(120),
+            range: Some(100000),
+            ..Default::default()
+        },
+        &default_client(),
+        &nice_rust::ShutdownSignal::new(),
     );
 }
+
+#[test]
+fn integration_shutdown_benchmark() {
+    // the signal is already set, so the outcome doesn't depend on how fast the field is
+    let shutdown = nice_rust::ShutdownSignal::new();
+    shutdown.trigger();
+    for on_shutdown in [
+        nice_rust::ShutdownAction::Checkpoint,
+        nice_rust::ShutdownAction::Release,
+        nice_rust::ShutdownAction::Submit,
+    ] {
+        assert!(matches!(
+            nice_rust::run(
+                &nice_rust::Config {
+                    mode: nice_rust::Mode::Detailed,
+                    benchmark: true,
+                    range: Some(100000),
+                    on_shutdown,
+                    ..Default::default()
+                },
+                &default_client(),
+                &shutdown,
+            ),
+            nice_rust::RunOutcome::Abandoned { id: 0 }
+        ));
+    }
+    assert!(matches!(
+        nice_rust::run(
+            &nice_rust::Config {
+                mode: nice_rust::Mode::Detailed,
+                benchmark: true,
+                range: Some(1000),
+                on_shutdown: nice_rust::ShutdownAction::Finish,
+                ..Default::default()
+            },
+            &default_client(),
+            &shutdown,
+        ),
+        nice_rust::RunOutcome::Completed(_)
+    ));
+}
+
+#[test]
+fn integration_shutdown_mid_field() {
+    // a field far too big to finish, so returning at all means processing stopped
+    let shutdown = nice_rust::ShutdownSignal::new();
+    let trigger = shutdown.clone();
+    let timer = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        trigger.trigger();
+    });
+    assert!(matches!(
+        nice_rust::run(
+            &nice_rust::Config {
+                mode: nice_rust::Mode::Detailed,
+                benchmark: true,
+                range: Some(1_000_000_000),
+                on_shutdown: nice_rust::ShutdownAction::Release,
+                ..Default::default()
+            },
+            &default_client(),
+            &shutdown,
+        ),
+        nice_rust::RunOutcome::Abandoned { id: 0 }
+    ));
+    timer.join().unwrap();
+}